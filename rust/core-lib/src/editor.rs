@@ -25,7 +25,7 @@ use xi_rope::rope::{LinesMetric, Rope, RopeInfo};
 use xi_rope::interval::Interval;
 use xi_rope::delta::{self, Delta, Transformer};
 use xi_rope::tree::Cursor;
-use xi_rope::engine::Engine;
+use xi_rope::engine::{Engine, RopeEngine};
 use xi_rope::spans::{Spans, SpansBuilder};
 use view::{Style, View};
 use word_boundaries::WordCursor;
@@ -56,7 +56,7 @@ pub struct Editor<W: Write> {
     /// The currently active view. This property is dynamically modified as events originating in
     /// different views arrive.
     view: View,
-    engine: Engine,
+    engine: RopeEngine,
     last_rev_id: usize,
     pristine_rev_id: usize,
     undo_group_id: usize,
@@ -18,10 +18,21 @@
 // Note: this data structure has nontrivial overlap with Subset in the rope
 // crate. Maybe we don't need both.
 
-use std::cmp::{min, max};
+use std::cmp::{min, max, Ordering};
+use std::iter::FromIterator;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+#[cfg(test)]
+use std::cell::Cell;
+
+// Counts the ranges probed while searching in `union_one_range`, so tests
+// can check the search is logarithmic.
+#[cfg(test)]
+thread_local!(static UNION_PROBES: Cell<usize> = Cell::new(0));
 
 pub struct IndexSet {
     ranges: Vec<(usize, usize)>,
+    max_ranges: Option<usize>,
 }
 
 pub fn remove_n_at<T: Clone>(v: &mut Vec<T>, index: usize, n: usize) {
@@ -41,6 +52,37 @@ impl IndexSet {
     pub fn new() -> IndexSet {
         IndexSet {
             ranges: Vec::new(),
+            max_ranges: None,
+        }
+    }
+
+    /// Create a set from a list of `(start, end)` ranges, which must be
+    /// non-empty, sorted, and neither overlapping nor adjacent (the form the
+    /// set itself keeps them in). Returns `None` if they are not.
+    pub fn from_sorted_disjoint(ranges: Vec<(usize, usize)>) -> Option<IndexSet> {
+        for (i, &(start, end)) in ranges.iter().enumerate() {
+            if start >= end || (i > 0 && ranges[i - 1].1 >= start) {
+                return None;
+            }
+        }
+        Some(IndexSet {
+            ranges: ranges,
+            max_ranges: None,
+        })
+    }
+
+    /// Create a new, empty set that holds at most `max_ranges` ranges.
+    ///
+    /// When a union would exceed the cap, the two ranges separated by the
+    /// smallest gap are merged (filling in the gap) until the set is back
+    /// under it. This trades precision for bounded memory: the set may then
+    /// cover indices that were never added, but never loses coverage of
+    /// indices that were.
+    pub fn with_max_ranges(max_ranges: usize) -> IndexSet {
+        assert!(max_ranges > 0, "IndexSet max_ranges must be positive");
+        IndexSet {
+            ranges: Vec::new(),
+            max_ranges: Some(max_ranges),
         }
     }
 
@@ -51,25 +93,172 @@ impl IndexSet {
 
     /// Add the range start..end to the set.
     pub fn union_one_range(&mut self, start: usize, end: usize) {
-        for i in 0..self.ranges.len() {
-            let (istart, iend) = self.ranges[i];
-            if start > iend {
-                continue;
-            } else if end < istart {
-                self.ranges.insert(i, (start, end));
-                return;
+        // The ranges are sorted and disjoint, so we can binary search for the
+        // first one that ends at or after `start`.
+        let i = match self.ranges.binary_search_by(|&(_, iend)| {
+            #[cfg(test)]
+            UNION_PROBES.with(|probes| probes.set(probes.get() + 1));
+            if iend < start { Ordering::Less } else { Ordering::Greater }
+        }) {
+            Ok(i) | Err(i) => i,
+        };
+        if i == self.ranges.len() {
+            self.ranges.push((start, end));
+            self.enforce_max_ranges();
+            return;
+        }
+        let (istart, _) = self.ranges[i];
+        if end < istart {
+            self.ranges.insert(i, (start, end));
+            self.enforce_max_ranges();
+            return;
+        }
+        self.ranges[i].0 = min(start, istart);
+        let mut j = i;
+        while j + 1 < self.ranges.len() && end >= self.ranges[j + 1].0 {
+            j += 1;
+        }
+        self.ranges[i].1 = max(end, self.ranges[j].1);
+        remove_n_at(&mut self.ranges, i + 1, j - i);
+    }
+
+    /// Add a single index to the set.
+    pub fn insert_point(&mut self, index: usize) {
+        self.union_one_range(index, index + 1);
+    }
+
+    /// Add many individual indices to the set. The points are sorted and
+    /// coalesced into ranges, which are then merged into the set in a single
+    /// pass, rather than doing a union per point.
+    pub fn extend_points<I: Iterator<Item=usize>>(&mut self, points: I) {
+        let mut points: Vec<usize> = points.collect();
+        points.sort();
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for ix in points {
+            if let Some(last) = ranges.last_mut() {
+                if ix <= last.1 {
+                    last.1 = max(last.1, ix + 1);
+                    continue;
+                }
+            }
+            ranges.push((ix, ix + 1));
+        }
+        self.union_sorted(&ranges);
+    }
+
+    /// Add many ranges to the set, returning how many indices were newly
+    /// covered as a result, for progress accounting. Indices the set already
+    /// covered are not counted. The ranges are expected to be sorted by
+    /// start, and are sorted first if not; empty ranges are ignored.
+    ///
+    /// If the set has a `max_ranges` cap, indices covered by filling in gaps
+    /// to stay under it are counted too.
+    pub fn union_counting<I: Iterator<Item=(usize, usize)>>(&mut self, ranges: I) -> usize {
+        let mut ranges: Vec<(usize, usize)> = ranges.filter(|&(start, end)| start < end).collect();
+        if !ranges.windows(2).all(|w| w[0].0 <= w[1].0) {
+            ranges.sort();
+        }
+        self.union_sorted(&ranges)
+    }
+
+    // Union a sorted list of non-empty ranges into the set, in one merge of
+    // the two lists. Returns the number of newly covered indices.
+    fn union_sorted(&mut self, ranges: &[(usize, usize)]) -> usize {
+        let old_len: usize = self.ranges.iter().map(|&(start, end)| end - start).sum();
+        let mut result: Vec<(usize, usize)> = Vec::with_capacity(self.ranges.len() + ranges.len());
+        let mut i = 0;
+        let mut j = 0;
+        while i < self.ranges.len() || j < ranges.len() {
+            let next = if j == ranges.len() || (i < self.ranges.len() && self.ranges[i].0 < ranges[j].0) {
+                i += 1;
+                self.ranges[i - 1]
             } else {
-                self.ranges[i].0 = min(start, istart);
-                let mut j = i;
-                while j + 1 < self.ranges.len() && end >= self.ranges[j + 1].0 {
-                    j += 1;
+                j += 1;
+                ranges[j - 1]
+            };
+            if let Some(last) = result.last_mut() {
+                if next.0 <= last.1 {
+                    last.1 = max(last.1, next.1);
+                    continue;
+                }
+            }
+            result.push(next);
+        }
+        self.ranges = result;
+        self.enforce_max_ranges();
+        let new_len: usize = self.ranges.iter().map(|&(start, end)| end - start).sum();
+        new_len - old_len
+    }
+
+    /// Return the smallest range in the set, without removing it.
+    pub fn peek_first(&self) -> Option<(usize, usize)> {
+        self.ranges.first().cloned()
+    }
+
+    /// Return the longest range in the set, or the first of them if several
+    /// are equally long.
+    pub fn largest_range(&self) -> Option<(usize, usize)> {
+        let mut result: Option<(usize, usize)> = None;
+        for &(start, end) in &self.ranges {
+            if result.map_or(true, |(s, e)| end - start > e - s) {
+                result = Some((start, end));
+            }
+        }
+        result
+    }
+
+    /// Remove and return the smallest range in the set. This shifts the
+    /// remaining ranges, so it is O(n) in their number.
+    pub fn pop_first(&mut self) -> Option<(usize, usize)> {
+        if self.ranges.is_empty() {
+            None
+        } else {
+            Some(self.ranges.remove(0))
+        }
+    }
+
+    /// Determine whether the index is in the set.
+    pub fn contains(&self, ix: usize) -> bool {
+        match self.ranges.binary_search_by(|&(_, iend)| {
+            if iend <= ix { Ordering::Less } else { Ordering::Greater }
+        }) {
+            Ok(i) | Err(i) => i < self.ranges.len() && self.ranges[i].0 <= ix,
+        }
+    }
+
+    /// Determine whether every index in `other` is also in this set.
+    pub fn covers(&self, other: &IndexSet) -> bool {
+        let mut i = 0;
+        for &(start, end) in &other.ranges {
+            if start >= end {
+                continue;
+            }
+            while i < self.ranges.len() && self.ranges[i].1 < end {
+                i += 1;
+            }
+            if i == self.ranges.len() || self.ranges[i].0 > start {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Merge the ranges separated by the smallest gaps until the set is within
+    // its `max_ranges` cap.
+    fn enforce_max_ranges(&mut self) {
+        if let Some(max_ranges) = self.max_ranges {
+            while self.ranges.len() > max_ranges {
+                let mut best = 0;
+                for i in 1..self.ranges.len() - 1 {
+                    let gap = self.ranges[i + 1].0 - self.ranges[i].1;
+                    if gap < self.ranges[best + 1].0 - self.ranges[best].1 {
+                        best = i;
+                    }
                 }
-                self.ranges[i].1 = max(end, self.ranges[j].1);
-                remove_n_at(&mut self.ranges, i + 1, j - i);
-                return;
+                self.ranges[best].1 = self.ranges[best + 1].1;
+                self.ranges.remove(best + 1);
             }
         }
-        self.ranges.push((start, end));
     }
 
     /// Return an iterator that yields start..end minus the coverage in this set.
@@ -85,12 +274,100 @@ impl IndexSet {
         }
     }
 
+    /// Return an iterator over the gaps in this set from `start` onward, for
+    /// scanning an open-ended region such as "everything invalid after line
+    /// N". Gaps are computed only as the iterator is polled, and iteration
+    /// stops at `hard_cap`. Equivalent to `minus_one_range(start, hard_cap)`.
+    pub fn gaps_from(&self, start: usize, hard_cap: usize) -> MinusIter {
+        self.minus_one_range(start, hard_cap)
+    }
+
+    /// Return the smallest index at or after `ix` that is not in the set.
+    pub fn first_gap_after(&self, ix: usize) -> usize {
+        match self.ranges.binary_search_by(|&(_, iend)| {
+            if iend <= ix { Ordering::Less } else { Ordering::Greater }
+        }) {
+            Ok(i) | Err(i) => {
+                if i < self.ranges.len() && self.ranges[i].0 <= ix {
+                    self.ranges[i].1
+                } else {
+                    ix
+                }
+            }
+        }
+    }
+
+    /// Return an iterator that partitions start..end into alternating covered
+    /// and uncovered segments, yielding `(is_covered, seg_start, seg_end)`.
+    pub fn segments(&self, start: usize, end: usize) -> SegmentIter {
+        let mut ranges = &self.ranges[..];
+        while !ranges.is_empty() && start >= ranges[0].1 {
+            ranges = &ranges[1..];
+        }
+        SegmentIter {
+            ranges: ranges,
+            start: start,
+            end: end,
+        }
+    }
+
+    /// The fraction of start..end that is covered by this set, for example
+    /// for displaying progress. An empty range counts as fully covered.
+    pub fn coverage_fraction(&self, start: usize, end: usize) -> f64 {
+        if start >= end {
+            return 1.0;
+        }
+        let covered: usize = self.segments(start, end)
+            .filter(|&(is_covered, _, _)| is_covered)
+            .map(|(_, seg_start, seg_end)| seg_end - seg_start)
+            .sum();
+        covered as f64 / (end - start) as f64
+    }
+
     #[cfg(test)]
     fn get_ranges(&self) -> &[(usize, usize)] {
         &self.ranges
     }
 }
 
+/// Collects ranges, such as those yielded by `minus_one_range`, into a set.
+/// Empty ranges are ignored. Ranges sorted by start are merged in a single
+/// pass; otherwise they are added one at a time.
+impl FromIterator<(usize, usize)> for IndexSet {
+    fn from_iter<I: IntoIterator<Item=(usize, usize)>>(iter: I) -> IndexSet {
+        let ranges: Vec<(usize, usize)> = iter.into_iter().filter(|&(start, end)| start < end).collect();
+        let mut result = IndexSet::new();
+        if ranges.windows(2).all(|w| w[0].0 <= w[1].0) {
+            result.union_sorted(&ranges);
+        } else {
+            for (start, end) in ranges {
+                result.union_one_range(start, end);
+            }
+        }
+        result
+    }
+}
+
+/// Serializes as a list of `[start, end]` pairs. The `max_ranges` cap, if
+/// any, is not persisted.
+impl Serialize for IndexSet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        self.ranges.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for IndexSet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let ranges = Vec::<(usize, usize)>::deserialize(deserializer)?;
+        IndexSet::from_sorted_disjoint(ranges)
+            .ok_or_else(|| D::Error::custom("index set ranges must be non-empty, sorted and disjoint"))
+    }
+}
+
 /// The iterator generated by `minus_one_range`.
 pub struct MinusIter<'a> {
     ranges: &'a [(usize, usize)],
@@ -119,9 +396,43 @@ impl<'a> Iterator for MinusIter<'a> {
     }
 }
 
+/// The iterator generated by `segments`.
+pub struct SegmentIter<'a> {
+    ranges: &'a [(usize, usize)],
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Iterator for SegmentIter<'a> {
+    type Item = (bool, usize, usize);
+
+    fn next(&mut self) -> Option<(bool, usize, usize)> {
+        if self.start >= self.end {
+            return None;
+        }
+        if self.ranges.is_empty() || self.end <= self.ranges[0].0 {
+            let result = (false, self.start, self.end);
+            self.start = self.end;
+            return Some(result);
+        }
+        let (rstart, rend) = self.ranges[0];
+        if self.start < rstart {
+            let result = (false, self.start, rstart);
+            self.start = rstart;
+            return Some(result);
+        }
+        let seg_end = min(rend, self.end);
+        let result = (true, self.start, seg_end);
+        self.start = seg_end;
+        self.ranges = &self.ranges[1..];
+        Some(result)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::IndexSet;
+    use super::{IndexSet, UNION_PROBES};
+    use serde_json;
 
     #[test]
     fn empty_behavior() {
@@ -188,4 +499,217 @@ mod tests {
         e.union_one_range(2, 10);
         assert_eq!(e.get_ranges(), &[(2, 10), (11, 12)]);
     }
+
+    #[test]
+    fn segments() {
+        let mut e = IndexSet::new();
+        e.union_one_range(3, 5);
+        e.union_one_range(7, 9);
+        assert_eq!(e.segments(0, 10).collect::<Vec<_>>(),
+            vec![(false, 0, 3), (true, 3, 5), (false, 5, 7), (true, 7, 9), (false, 9, 10)]);
+        assert_eq!(e.segments(4, 8).collect::<Vec<_>>(),
+            vec![(true, 4, 5), (false, 5, 7), (true, 7, 8)]);
+        assert_eq!(e.segments(5, 5).collect::<Vec<_>>(), vec![]);
+        assert_eq!(IndexSet::new().segments(0, 10).collect::<Vec<_>>(), vec![(false, 0, 10)]);
+    }
+
+    #[test]
+    fn union_large() {
+        let mut e = IndexSet::new();
+        for i in 0..10_000 {
+            e.union_one_range(4 * i, 4 * i + 2);
+        }
+        assert_eq!(e.get_ranges().len(), 10_000);
+        UNION_PROBES.with(|probes| probes.set(0));
+        e.union_one_range(20_001, 20_005);
+        UNION_PROBES.with(|probes| assert!(probes.get() <= 15));
+        assert_eq!(e.get_ranges().len(), 9_999);
+        assert_eq!(&e.get_ranges()[4_999..5_002], &[(19_996, 19_998), (20_000, 20_006), (20_008, 20_010)]);
+        e.union_one_range(2, 39_999);
+        assert_eq!(e.get_ranges(), &[(0, 39_999)]);
+    }
+
+    #[test]
+    fn max_ranges() {
+        let mut e = IndexSet::with_max_ranges(4);
+        let mut added = Vec::new();
+        for i in 0..100 {
+            // scatter disjoint ranges with varying gaps
+            let start = (i * 37) % 100 * 10 + i % 3;
+            e.union_one_range(start, start + 2);
+            added.push((start, start + 2));
+            assert!(e.get_ranges().len() <= 4);
+        }
+        for &(start, end) in &added {
+            for ix in start..end {
+                assert!(e.contains(ix));
+            }
+        }
+        let mut e = IndexSet::with_max_ranges(2);
+        e.union_one_range(0, 1);
+        e.union_one_range(10, 11);
+        e.union_one_range(3, 4);
+        assert_eq!(e.get_ranges(), &[(0, 4), (10, 11)]);
+        assert!(e.contains(2));
+        assert!(!e.contains(4));
+        assert!(!e.contains(9));
+    }
+
+    #[test]
+    fn covers() {
+        let mut e = IndexSet::new();
+        e.union_one_range(3, 5);
+        e.union_one_range(7, 12);
+        let mut sub = IndexSet::new();
+        assert!(e.covers(&sub));
+        sub.union_one_range(4, 5);
+        sub.union_one_range(8, 10);
+        sub.union_one_range(11, 12);
+        assert!(e.covers(&sub));
+        assert!(!sub.covers(&e));
+
+        let mut same = IndexSet::new();
+        same.union_one_range(7, 12);
+        same.union_one_range(3, 5);
+        assert!(e.covers(&same));
+        assert!(same.covers(&e));
+
+        let mut over = IndexSet::new();
+        over.union_one_range(3, 5);
+        over.union_one_range(6, 8);
+        assert!(!e.covers(&over));
+        over.clear();
+        over.union_one_range(11, 13);
+        assert!(!e.covers(&over));
+    }
+
+    #[test]
+    fn gaps_from() {
+        let mut e = IndexSet::new();
+        e.union_one_range(3, 5);
+        e.union_one_range(7, 9);
+        e.union_one_range(12, 20);
+        for &(start, cap) in &[(0, 30), (4, 15), (8, 8), (21, 25), (5, 7)] {
+            assert_eq!(e.minus_one_range(start, cap).collect::<Vec<_>>(),
+                e.gaps_from(start, cap).collect::<Vec<_>>());
+        }
+        assert_eq!(Some((9, 12)), e.gaps_from(7, usize::max_value()).next());
+    }
+
+    #[test]
+    fn first_gap_after() {
+        let mut e = IndexSet::new();
+        assert_eq!(4, e.first_gap_after(4));
+        e.union_one_range(3, 5);
+        e.union_one_range(5, 9);
+        e.union_one_range(12, 20);
+        assert_eq!(0, e.first_gap_after(0));
+        assert_eq!(9, e.first_gap_after(3));
+        assert_eq!(9, e.first_gap_after(8));
+        assert_eq!(9, e.first_gap_after(9));
+        assert_eq!(20, e.first_gap_after(12));
+        assert_eq!(25, e.first_gap_after(25));
+    }
+
+    #[test]
+    fn from_sorted_disjoint() {
+        let e = IndexSet::from_sorted_disjoint(vec![(1, 3), (5, 8)]).unwrap();
+        assert_eq!(e.get_ranges(), &[(1, 3), (5, 8)]);
+        assert!(IndexSet::from_sorted_disjoint(vec![]).is_some());
+        assert!(IndexSet::from_sorted_disjoint(vec![(5, 8), (1, 3)]).is_none());
+        assert!(IndexSet::from_sorted_disjoint(vec![(1, 3), (3, 8)]).is_none());
+        assert!(IndexSet::from_sorted_disjoint(vec![(3, 3)]).is_none());
+    }
+
+    #[test]
+    fn serde() {
+        let mut e = IndexSet::new();
+        e.union_one_range(3, 5);
+        e.union_one_range(7, 12);
+        let json = serde_json::to_string(&e).unwrap();
+        assert_eq!("[[3,5],[7,12]]", json);
+        let e2: IndexSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(e.get_ranges(), e2.get_ranges());
+
+        assert!(serde_json::from_str::<IndexSet>("[[3,8],[7,12]]").is_err());
+        assert!(serde_json::from_str::<IndexSet>("[[7,12],[3,5]]").is_err());
+    }
+
+    #[test]
+    fn extend_points() {
+        let mut e = IndexSet::new();
+        e.extend_points(vec![7, 4, 3, 5, 4].into_iter());
+        assert_eq!(e.get_ranges(), &[(3, 6), (7, 8)]);
+        e.insert_point(6);
+        assert_eq!(e.get_ranges(), &[(3, 8)]);
+        e.union_one_range(10, 12);
+        e.union_one_range(20, 22);
+        e.extend_points(vec![0, 9, 13, 15, 16, 23].into_iter());
+        assert_eq!(e.get_ranges(), &[(0, 1), (3, 8), (9, 12), (13, 14), (15, 17), (20, 22), (23, 24)]);
+        e.extend_points(vec![22, 12].into_iter());
+        assert_eq!(e.get_ranges(), &[(0, 1), (3, 8), (9, 14), (15, 17), (20, 24)]);
+    }
+
+    #[test]
+    fn pop_first() {
+        let mut e = IndexSet::new();
+        assert_eq!(None, e.peek_first());
+        assert_eq!(None, e.pop_first());
+        e.union_one_range(20, 25);
+        e.union_one_range(3, 5);
+        e.union_one_range(10, 12);
+        assert_eq!(Some((3, 5)), e.peek_first());
+        assert_eq!(Some((3, 5)), e.pop_first());
+        assert_eq!(Some((10, 12)), e.pop_first());
+        assert_eq!(Some((20, 25)), e.peek_first());
+        assert_eq!(Some((20, 25)), e.pop_first());
+        assert_eq!(None, e.pop_first());
+    }
+
+    #[test]
+    fn coverage_fraction() {
+        let mut e = IndexSet::new();
+        e.union_one_range(10, 20);
+        e.union_one_range(30, 35);
+        assert_eq!(1.0, e.coverage_fraction(12, 18));
+        assert_eq!(0.5, e.coverage_fraction(15, 25));
+        assert_eq!(0.5, e.coverage_fraction(10, 40));
+        assert_eq!(0.0, e.coverage_fraction(0, 10));
+        assert_eq!(1.0, e.coverage_fraction(5, 5));
+    }
+
+    #[test]
+    fn from_iter() {
+        let mut e = IndexSet::new();
+        e.union_one_range(10, 20);
+        e.union_one_range(30, 35);
+        let gaps: IndexSet = e.minus_one_range(0, 40).collect();
+        assert_eq!(&[(0, 10), (20, 30), (35, 40)], gaps.get_ranges());
+        let unsorted: IndexSet = vec![(30, 35), (5, 5), (10, 20), (15, 31)].into_iter().collect();
+        assert_eq!(&[(10, 35)], unsorted.get_ranges());
+    }
+
+    #[test]
+    fn largest_range() {
+        let mut e = IndexSet::new();
+        assert_eq!(None, e.largest_range());
+        e.union_one_range(0, 2);
+        e.union_one_range(5, 10);
+        e.union_one_range(12, 13);
+        assert_eq!(Some((5, 10)), e.largest_range());
+        e.union_one_range(20, 25);
+        assert_eq!(Some((5, 10)), e.largest_range());
+    }
+
+    #[test]
+    fn union_counting() {
+        let mut e = IndexSet::new();
+        e.union_one_range(10, 20);
+        assert_eq!(5, e.union_counting(vec![(15, 25)].into_iter()));
+        assert_eq!(&[(10, 25)], e.get_ranges());
+        assert_eq!(0, e.union_counting(vec![(12, 18), (20, 22)].into_iter()));
+        assert_eq!(13, e.union_counting(vec![(30, 32), (0, 12), (31, 33)].into_iter()));
+        assert_eq!(&[(0, 25), (30, 33)], e.get_ranges());
+        assert_eq!(0, e.union_counting(vec![(40, 40)].into_iter()));
+    }
 }
@@ -17,11 +17,18 @@
 //! shared across multiple subsystems.
 
 use interval::Interval;
-use tree::{Node, NodeInfo, TreeBuilder};
+use tree::{Cursor, Node, NodeInfo, TreeBuilder};
+use rope::{LineEnding, LinesMetric, Rope, RopeInfo};
 use subset::{Subset, SubsetBuilder};
-use std::cmp::min;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::{max, min};
+use std::mem;
 use std::ops::Deref;
+use std::slice;
+use std::error;
 use std::fmt;
+use std::str;
 
 #[derive(Clone)]
 enum DeltaElement<N: NodeInfo> {
@@ -43,6 +50,80 @@ pub struct Delta<N: NodeInfo> {
     base_len: usize,
 }
 
+/// A coarse classification of the effect of a `Delta`, as returned by
+/// `Delta::classify`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeltaClass {
+    /// The delta copies the whole base document and inserts nothing.
+    Identity,
+    /// The delta copies the whole base document and inserts something.
+    PureInsert,
+    /// The delta inserts nothing and deletes something.
+    PureDelete,
+    /// The delta both inserts and deletes.
+    Replace,
+}
+
+/// A single replacement of a range of the old document, in the style of the
+/// Language Server Protocol's `TextEdit`. Produced by `Delta::to_text_edits`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TextEdit {
+    /// The start of the replaced range, as a byte offset in the old document.
+    pub start: usize,
+    /// The end of the replaced range, as a byte offset in the old document.
+    pub end: usize,
+    /// The text replacing the range.
+    pub new_text: String,
+}
+
+/// A single-character edit, as produced by `Delta::to_micro_ops`. Offsets
+/// are in bytes, in the document as it stands when the operation is applied.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MicroOp {
+    /// Insert `ch` at offset `at`.
+    InsertChar { at: usize, ch: char },
+    /// Delete the character at offset `at`.
+    DeleteChar { at: usize },
+}
+
+/// The origin of a span of the new document, as returned by `Delta::annotate`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Annotation {
+    /// The span is copied from the old document, starting at `old_start`.
+    Unchanged { old_start: usize },
+    /// The span is newly inserted.
+    Inserted,
+}
+
+/// Which side of a concurrent insertion at the same location an insert
+/// should land on when transforming through it. Used by
+/// `InsertDelta::transform_expand_biased`.
+///
+/// The `Engine` resolves concurrent edits by priority: an edit with a higher
+/// (or equal) priority than the one it is being rebased over gets `Right`,
+/// otherwise `Left`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertBias {
+    /// Land before the insertions in the coordinate transform.
+    Left,
+    /// Land after the insertions in the coordinate transform.
+    Right,
+}
+
+/// What `Delta::remap_range` does with a range that the delta edits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RemapPolicy {
+    /// Keep whatever part of the range survives, trimming deleted text from
+    /// it; a range that is deleted entirely becomes empty.
+    Clamp,
+    /// Drop the range if any of it is deleted or anything is inserted
+    /// strictly inside it.
+    DropIfTouched,
+    /// Drop the range if any of it is deleted. Text inserted inside it
+    /// becomes part of it.
+    DropIfDeleted,
+}
+
 /// A struct marking that a Delta contains only insertions. That is, it copies
 /// all of the old document in the same order. It has a `Deref` impl so all
 /// normal `Delta` methods can also be used on it.
@@ -59,6 +140,36 @@ impl<N: NodeInfo> Delta<N> {
         builder.build()
     }
 
+    /// Build a delta that deletes `deletions` from a document of length
+    /// `base_len`, and inserts each rope in `inserts` at the paired offset in
+    /// that document. The offsets must be sorted and at most `base_len`.
+    /// Inserts at the same offset are inserted in order.
+    pub fn from_subset_and_inserts(base_len: usize, deletions: &Subset,
+            inserts: Vec<(usize, Node<N>)>) -> Delta<N> {
+        for (i, &(offset, _)) in inserts.iter().enumerate() {
+            assert!(offset <= base_len, "insert offset {} past end of document", offset);
+            assert!(i == 0 || inserts[i - 1].0 <= offset, "insert offsets must be sorted");
+        }
+        let mut els = Vec::new();
+        let mut inserts = inserts.into_iter().peekable();
+        for (beg, end) in deletions.complement_iter(base_len) {
+            let mut beg = beg;
+            while inserts.peek().map_or(false, |&(offset, _)| offset < end) {
+                let (offset, n) = inserts.next().unwrap();
+                if offset > beg {
+                    push_copy(&mut els, beg, offset);
+                    beg = offset;
+                }
+                push_insert(&mut els, n);
+            }
+            push_copy(&mut els, beg, end);
+        }
+        for (_, n) in inserts {
+            push_insert(&mut els, n);
+        }
+        Delta { els: els, base_len: base_len }
+    }
+
     /// Apply the delta to the given rope. May not work well if the length of the rope
     /// is not compatible with the construction of the delta.
     pub fn apply(&self, base: &Node<N>) -> Node<N> {
@@ -75,6 +186,72 @@ impl<N: NodeInfo> Delta<N> {
         b.build()
     }
 
+    /// Apply the delta to the given rope, also returning a `Transformer` for
+    /// mapping coordinates through the same delta, for callers that update
+    /// both text and positions such as selections.
+    pub fn apply_with_transformer<'a>(&'a self, base: &Node<N>) -> (Node<N>, Transformer<'a, N>) {
+        (self.apply(base), Transformer::new(self))
+    }
+
+    /// Apply the delta to the given rope, and map selection regions through
+    /// it in the same step. Starts land before text inserted at them and
+    /// ends after it; a selection whose text is deleted entirely collapses
+    /// to a caret where the deletion was, after any text replacing it.
+    pub fn apply_remapping_selections(&self, base: &Node<N>, selections: &[Interval]) -> (Node<N>, Vec<Interval>) {
+        let (text, mut transformer) = self.apply_with_transformer(base);
+        let regions = selections.iter().map(|&iv| {
+            let (start, end) = iv.start_end();
+            let new_start = transformer.transform(start, false);
+            let deleted = start < end &&
+                self.remap_range(iv, RemapPolicy::Clamp).map_or(false, |new_iv| new_iv.is_empty());
+            if deleted {
+                Interval::new_closed_open(new_start, new_start)
+            } else {
+                Interval::new_closed_open(new_start, transformer.transform(end, true))
+            }
+        }).collect();
+        (text, regions)
+    }
+
+    /// Apply only the deletions of the delta to the given rope, omitting its
+    /// inserts. This is the same as deleting `self.factor().1` from `base`.
+    pub fn apply_deletes_only(&self, base: &Node<N>) -> Node<N> {
+        debug_assert_eq!(base.len(), self.base_len, "must apply Delta to Node of correct length");
+        let mut b = TreeBuilder::new();
+        for elem in &self.els {
+            if let DeltaElement::Copy(beg, end) = *elem {
+                base.push_subseq(&mut b, Interval::new_closed_open(beg, end));
+            }
+        }
+        b.build()
+    }
+
+    /// Apply the delta to the given rope, also returning the concatenation,
+    /// in order, of the base content that the delta deletes. Together with
+    /// the positions of the inserts, this is enough to invert the delta.
+    pub fn apply_recording_deletions(&self, base: &Node<N>) -> (Node<N>, Node<N>) {
+        debug_assert_eq!(base.len(), self.base_len, "must apply Delta to Node of correct length");
+        let mut b = TreeBuilder::new();
+        let mut deleted = TreeBuilder::new();
+        let mut last = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > last {
+                        base.push_subseq(&mut deleted, Interval::new_closed_open(last, beg));
+                    }
+                    base.push_subseq(&mut b, Interval::new_closed_open(beg, end));
+                    last = end;
+                }
+                DeltaElement::Insert(ref n) => b.push(n.clone())
+            }
+        }
+        if last < self.base_len {
+            base.push_subseq(&mut deleted, Interval::new_closed_open(last, self.base_len));
+        }
+        (b.build(), deleted.build())
+    }
+
     /// Factor the delta into an insert-only delta and a subset representing deletions.
     /// Applying the insert then the delete yields the same result as the original delta:
     ///
@@ -89,10 +266,95 @@ impl<N: NodeInfo> Delta<N> {
     /// }
     /// ```
     pub fn factor(self) -> (InsertDelta<N>, Subset) {
+        let (ins, del, _) = self.factor_full();
+        (ins, del)
+    }
+
+    /// Decompose the delta into the subset of the base that it deletes, the
+    /// subset of the new document that it inserts, and the inserted ropes in
+    /// order (omitting empty ones). Unlike `factor`, the inserted text is
+    /// kept apart from the structure, so it can be stored separately; the
+    /// delta can be rebuilt with `from_subsets`.
+    pub fn to_subsets(&self) -> (Subset, Subset, Vec<Node<N>>) {
+        let mut dels = SubsetBuilder::new();
+        let mut ins = SubsetBuilder::new();
+        let mut ropes = Vec::new();
+        let mut last_end = 0;
+        let mut x = 0;  // coordinate within the new document
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    dels.add_range(last_end, beg);
+                    last_end = end;
+                    x += end - beg;
+                }
+                DeltaElement::Insert(ref n) => {
+                    if n.len() > 0 {
+                        ins.add_range(x, x + n.len());
+                        x += n.len();
+                        ropes.push(n.clone());
+                    }
+                }
+            }
+        }
+        dels.add_range(last_end, self.base_len);
+        (dels.build(), ins.build(), ropes)
+    }
+
+    /// Rebuild a delta from the parts returned by `to_subsets`. Inserts next
+    /// to a deletion may end up on the other side of it, which gives the
+    /// same result when applied. Panics if the ropes don't fill `insertions`
+    /// exactly.
+    pub fn from_subsets(base_len: usize, deletions: &Subset, insertions: &Subset,
+            ropes: Vec<Node<N>>) -> Delta<N> {
+        let kept: Vec<(usize, usize)> = deletions.complement_iter(base_len).collect();
+        let mut ropes = ropes.into_iter();
+        let mut inserts = Vec::new();
+        let mut inserted_before = 0;
+        let mut kept_ix = 0;
+        let mut kept_before = 0;  // kept characters before `kept[kept_ix]`
+        for &(beg, end) in insertions._ranges() {
+            // Find the base offset after the first `beg - inserted_before`
+            // kept characters.
+            let k = beg - inserted_before;
+            while kept_ix < kept.len() && k > kept_before + kept[kept_ix].1 - kept[kept_ix].0 {
+                kept_before += kept[kept_ix].1 - kept[kept_ix].0;
+                kept_ix += 1;
+            }
+            let offset = if kept_ix < kept.len() { kept[kept_ix].0 + k - kept_before } else { base_len };
+            let mut remaining = end - beg;
+            while remaining > 0 {
+                let n = ropes.next().expect("fewer inserted ropes than insertions");
+                assert!(n.len() <= remaining, "inserted ropes don't match insertions");
+                remaining -= n.len();
+                inserts.push((offset, n));
+            }
+            inserted_before += end - beg;
+        }
+        assert!(ropes.next().is_none(), "more inserted ropes than insertions");
+        Delta::from_subset_and_inserts(base_len, deletions, inserts)
+    }
+
+    /// Like `factor`, but also returns the inserted subset of the insert-only
+    /// delta, computed in the same pass:
+    ///
+    /// ```no_run
+    /// # use xi_rope::rope::RopeInfo;
+    /// # use xi_rope::delta::Delta;
+    /// fn test_factor_full(d : &Delta<RopeInfo>) {
+    ///     let (ins, del) = d.clone().factor();
+    ///     let (_, del2, inserted) = d.clone().factor_full();
+    ///     assert_eq!(del, del2);
+    ///     assert_eq!(ins.inserted_subset(), inserted);
+    /// }
+    /// ```
+    pub fn factor_full(self) -> (InsertDelta<N>, Subset, Subset) {
         let mut ins = Vec::new();
         let mut sb = SubsetBuilder::new();
+        let mut isb = SubsetBuilder::new();
         let mut b1 = 0;
         let mut e1 = 0;
+        let mut x = 0;  // coordinate within the result of the insert-only delta
         for elem in self.els {
             match elem {
                 DeltaElement::Copy(b, e) => {
@@ -102,8 +364,11 @@ impl<N: NodeInfo> Delta<N> {
                 DeltaElement::Insert(n) => {
                     if e1 > b1 {
                         ins.push(DeltaElement::Copy(b1, e1));
+                        x += e1 - b1;
                     }
                     b1 = e1;
+                    isb.add_range(x, x + n.len());
+                    x += n.len();
                     ins.push(DeltaElement::Insert(n));
                 }
             }
@@ -112,7 +377,7 @@ impl<N: NodeInfo> Delta<N> {
             ins.push(DeltaElement::Copy(b1, self.base_len));
         }
         sb.add_range(e1, self.base_len);
-        (InsertDelta(Delta { els: ins, base_len: self.base_len }), sb.build())
+        (InsertDelta(Delta { els: ins, base_len: self.base_len }), sb.build(), isb.build())
     }
 
     /// Synthesize a delta from a "union string" and two subsets, an old set
@@ -122,6 +387,9 @@ impl<N: NodeInfo> Delta<N> {
     /// also think of these as a set of insertions and one of deletions, with
     /// overlap doing nothing. This is basically the inverse of `factor`.
     ///
+    /// The result is canonical, as produced by `coalesce`: contiguous copies
+    /// and adjacent inserts are merged, so equal inputs give identical deltas.
+    ///
     /// ```no_run
     /// # use xi_rope::rope::{Rope, RopeInfo};
     /// # use xi_rope::delta::Delta;
@@ -158,22 +426,9 @@ impl<N: NodeInfo> Delta<N> {
                 if last_old.is_some() && last_old.unwrap().0 <= beg {
                     let (ib, ie) = last_old.unwrap();
                     let end = min(e, ie);
-                    // Try to merge contiguous Copys in the output
                     let xbeg = beg + x - ib;  // "beg - ib + x" better for overflow?
                     let xend = end + x - ib;  // ditto
-                    let merged = if let Some(&mut DeltaElement::Copy(_, ref mut le)) = els.last_mut() {
-                        if *le == xbeg {
-                            *le = xend;
-                            true
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    };
-                    if !merged {
-                        els.push(DeltaElement::Copy(xbeg, xend));
-                    }
+                    push_copy(&mut els, xbeg, xend);
                     beg = end;
                 } else { // if the character at beg isn't in the old text, then we Insert
                     // Insert up until the next old range we could Copy from, or the end of this segment
@@ -181,13 +436,16 @@ impl<N: NodeInfo> Delta<N> {
                     if let Some((ib, _)) = last_old {
                         end = min(end, ib)
                     }
-                    // Note: could try to aggregate insertions, but not sure of the win.
-                    els.push(DeltaElement::Insert(s.subseq(Interval::new_closed_open(beg, end))));
+                    push_insert(&mut els, s.subseq(Interval::new_closed_open(beg, end)));
                     beg = end;
                 }
             }
         }
-        Delta { els: els, base_len: base_len }
+        let delta = Delta { els: els, base_len: base_len };
+        debug_assert_eq!(delta.base_len, old_dels.len_after_delete(s.len()));
+        debug_assert_eq!(delta.new_document_len(), new_dels.len_after_delete(s.len()),
+            "synthesized delta has the wrong length");
+        delta
     }
 
     /// Produce a summary of the delta. Everything outside the returned interval
@@ -199,7 +457,28 @@ impl<N: NodeInfo> Delta<N> {
     /// `new_s = self.apply(s)`
     ///
     /// `new_s = simple_edit(iv, new_s.subseq(iv.start(), iv.start() + new_len), s.len()).apply(s)`
+    ///
+    /// A delta that only inserts into an empty base has the summary
+    /// `(0..0, inserted_len)`, and one that deletes everything has
+    /// `(0..base_len, 0)`. Panics if the delta is malformed; see
+    /// `summary_checked`.
     pub fn summary(&self) -> (Interval, usize) {
+        self.summary_checked().expect("summary of a malformed delta")
+    }
+
+    /// Like `summary`, but returns `None` if the delta is malformed: its
+    /// copied ranges are out of order, overlap, or extend past the end of
+    /// the base.
+    pub fn summary_checked(&self) -> Option<(Interval, usize)> {
+        let mut last_end = 0;
+        for el in &self.els {
+            if let DeltaElement::Copy(beg, end) = *el {
+                if beg < last_end || end < beg || end > self.base_len {
+                    return None;
+                }
+                last_end = end;
+            }
+        }
         let mut els = self.els.as_slice();
         let mut iv_start = 0;
         if let Some((&DeltaElement::Copy(0, end), rest)) = els.split_first() {
@@ -213,7 +492,445 @@ impl<N: NodeInfo> Delta<N> {
                 els = init;
             }
         }
-        (Interval::new_closed_open(iv_start, iv_end), Delta::total_element_len(els))
+        Some((Interval::new_closed_open(iv_start, iv_end), Delta::total_element_len(els)))
+    }
+
+    /// The number of bytes at the start of the base document that the delta
+    /// leaves untouched.
+    pub fn unchanged_prefix_len(&self) -> usize {
+        match self.els.first() {
+            Some(&DeltaElement::Copy(0, end)) => end,
+            _ => 0,
+        }
+    }
+
+    /// The number of bytes at the end of the base document that the delta
+    /// leaves untouched.
+    pub fn unchanged_suffix_len(&self) -> usize {
+        match self.els.last() {
+            Some(&DeltaElement::Copy(beg, end)) if end == self.base_len => end - beg,
+            _ => 0,
+        }
+    }
+
+    /// Determine whether the delta only appends to the end of the document:
+    /// it copies the whole base and then inserts something, without
+    /// deleting or inserting anywhere else.
+    pub fn is_append_only(&self) -> bool {
+        let inserts = match self.els.split_first() {
+            Some((&DeltaElement::Copy(0, end), rest)) if end == self.base_len => rest,
+            _ if self.base_len == 0 => self.els.as_slice(),
+            _ => return false,
+        };
+        !inserts.is_empty() && inserts.iter().all(|elem| match *elem {
+            DeltaElement::Insert(_) => true,
+            DeltaElement::Copy(..) => false,
+        })
+    }
+
+    /// Determine whether the new document has the same length as the base.
+    pub fn is_length_preserving(&self) -> bool {
+        self.new_document_len() == self.base_len
+    }
+
+    /// Determine whether the delta only overwrites text in place, as in
+    /// overtype mode: each region it changes is replaced by text of the same
+    /// length, so no unchanged text moves.
+    pub fn is_in_place_overwrite(&self) -> bool {
+        let mut last = 0;
+        let mut inserted = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg - last != inserted {
+                        return false;
+                    }
+                    last = end;
+                    inserted = 0;
+                }
+                DeltaElement::Insert(ref n) => inserted += n.len(),
+            }
+        }
+        self.base_len - last == inserted
+    }
+
+    /// Classify the delta according to whether it inserts and/or deletes.
+    pub fn classify(&self) -> DeltaClass {
+        let mut has_insert = false;
+        let mut has_delete = false;
+        let mut last = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > last {
+                        has_delete = true;
+                    }
+                    last = end;
+                }
+                DeltaElement::Insert(ref n) => {
+                    if n.len() > 0 {
+                        has_insert = true;
+                    }
+                }
+            }
+        }
+        if last < self.base_len {
+            has_delete = true;
+        }
+        match (has_insert, has_delete) {
+            (false, false) => DeltaClass::Identity,
+            (true, false) => DeltaClass::PureInsert,
+            (false, true) => DeltaClass::PureDelete,
+            (true, true) => DeltaClass::Replace,
+        }
+    }
+
+    /// Determine whether the delta leaves every document unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.classify() == DeltaClass::Identity
+    }
+
+    // The regions of the base that the delta changes, each a maximal run of
+    // deletions and insertions between copies, as `(start, end)` offsets.
+    // An insertion with nothing deleted gives an empty region.
+    fn changed_regions(&self) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        let mut last = 0;
+        let mut inserted = false;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > last || inserted {
+                        result.push((last, beg));
+                    }
+                    last = end;
+                    inserted = false;
+                }
+                DeltaElement::Insert(_) => inserted = true,
+            }
+        }
+        if self.base_len > last || inserted {
+            result.push((last, self.base_len));
+        }
+        result
+    }
+
+    /// Determine whether the two deltas, which share a base, commute: that
+    /// applying either one and then the other transformed past it gives the
+    /// same document. This is conservative; it returns true only when the
+    /// regions the deltas change are disjoint and don't touch, so it may
+    /// return false for deltas that do commute.
+    pub fn commutes_with(&self, other: &Delta<N>) -> bool {
+        if self.base_len != other.base_len {
+            return false;
+        }
+        let a = self.changed_regions();
+        let b = other.changed_regions();
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i].0 <= b[j].1 && b[j].0 <= a[i].1 {
+                return false;
+            }
+            if a[i].1 < b[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        true
+    }
+
+    /// Apply the delta to the given rope, borrowing the rope rather than
+    /// building a new one when the delta is the identity.
+    pub fn apply_cow<'a>(&self, base: &'a Node<N>) -> Cow<'a, Node<N>> {
+        if self.is_identity() {
+            Cow::Borrowed(base)
+        } else {
+            Cow::Owned(self.apply(base))
+        }
+    }
+
+    /// Map offsets in the base document to offsets in the new document. A
+    /// mark strictly inside a deleted range is lost and maps to `None`;
+    /// otherwise it is transformed as by `Transformer::transform`, with
+    /// `after` choosing which side of an insertion at the mark it lands on.
+    pub fn map_bookmarks(&self, marks: &[usize], after: bool) -> Vec<Option<usize>> {
+        let mut gaps = Vec::new();
+        let mut last = 0;
+        for elem in &self.els {
+            if let DeltaElement::Copy(beg, end) = *elem {
+                if beg > last {
+                    gaps.push((last, beg));
+                }
+                last = end;
+            }
+        }
+        if last < self.base_len {
+            gaps.push((last, self.base_len));
+        }
+        let mut transformer = Transformer::new(self);
+        marks.iter().map(|&mark| {
+            if gaps.iter().any(|&(beg, end)| beg < mark && mark < end) {
+                None
+            } else {
+                Some(transformer.transform(mark, after))
+            }
+        }).collect()
+    }
+
+    /// Map a range of the base document, such as an annotation, to the
+    /// corresponding range of the new document, or `None` if `policy` drops
+    /// it. The interval is treated as half-open. Insertions at either end of
+    /// the range are not included in the result.
+    pub fn remap_range(&self, iv: Interval, policy: RemapPolicy) -> Option<Interval> {
+        let (start, end) = iv.start_end();
+        let mut new_range = None;
+        let mut copied = 0;
+        let mut inserted_inside = false;
+        let mut offset = 0;
+        let mut base_offset = 0;
+        for el in &self.els {
+            match *el {
+                DeltaElement::Copy(beg, e) => {
+                    let (lo, hi) = (max(beg, start), min(e, end));
+                    if lo < hi {
+                        let (new_lo, new_hi) = (offset + lo - beg, offset + hi - beg);
+                        new_range = Some(new_range.map_or((new_lo, new_hi), |(s, _)| (s, new_hi)));
+                        copied += hi - lo;
+                    }
+                    offset += e - beg;
+                    base_offset = e;
+                }
+                DeltaElement::Insert(ref n) => {
+                    inserted_inside |= start < base_offset && base_offset < end && n.len() > 0;
+                    offset += n.len();
+                }
+            }
+        }
+        let deleted = copied < end - start;
+        match policy {
+            RemapPolicy::DropIfDeleted if deleted => return None,
+            RemapPolicy::DropIfTouched if deleted || inserted_inside => return None,
+            _ => (),
+        }
+        let (new_start, new_end) = new_range.unwrap_or_else(|| {
+            let ix = Transformer::new(self).transform(start, false);
+            (ix, ix)
+        });
+        Some(Interval::new_closed_open(new_start, new_end))
+    }
+
+    /// Partition the delta into at most `k` sub-deltas over disjoint windows
+    /// of the base document, of roughly equal size. Windows are only split
+    /// inside copied regions, so each deletion and insertion falls entirely
+    /// in one window. Each sub-delta applies to the contents of its window,
+    /// and concatenating the results gives the same result as `self.apply`.
+    pub fn partition(&self, k: usize) -> Vec<(Interval, Delta<N>)> {
+        assert!(k > 0, "partition requires k > 0");
+        let mut result = Vec::new();
+        let mut els = Vec::new();
+        let mut ws = 0;  // start of the current window
+        let mut i = 1;  // index of the next target cut
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    let mut beg = beg;
+                    while i < k {
+                        let target = self.base_len * i / k;
+                        if target >= end {
+                            break;
+                        }
+                        let cut = if target > beg { target } else { beg };
+                        if cut > beg {
+                            els.push(DeltaElement::Copy(beg - ws, cut - ws));
+                        }
+                        if cut > ws {
+                            let window = Delta { els: els, base_len: cut - ws };
+                            result.push((Interval::new_closed_open(ws, cut), window));
+                            els = Vec::new();
+                            ws = cut;
+                        }
+                        beg = cut;
+                        i += 1;
+                    }
+                    if end > beg {
+                        els.push(DeltaElement::Copy(beg - ws, end - ws));
+                    }
+                }
+                DeltaElement::Insert(ref n) => els.push(DeltaElement::Insert(n.clone())),
+            }
+        }
+        if self.base_len > ws || result.is_empty() {
+            let window = Delta { els: els, base_len: self.base_len - ws };
+            result.push((Interval::new_closed_open(ws, self.base_len), window));
+        }
+        result
+    }
+
+    /// Restrict the delta to the range `lo..hi` of the base document, giving
+    /// a delta with a base length of `hi - lo`. Copies are clipped to the
+    /// range. An insert, whose base position is the end of the copy before it
+    /// (or 0), is kept if that position is in `lo..hi`, or if it is `hi` and
+    /// `hi` is the end of the document; that is, inserts belong to the range
+    /// after them. If the delta only changes text within `lo..hi`, applying
+    /// the result to that range of the base gives the corresponding range of
+    /// `self.apply(base)`.
+    pub fn restrict_to_base_range(&self, lo: usize, hi: usize) -> Delta<N> {
+        assert!(lo <= hi && hi <= self.base_len, "range out of bounds");
+        let mut els = Vec::new();
+        let mut last = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if max(beg, lo) < min(end, hi) {
+                        push_copy(&mut els, max(beg, lo) - lo, min(end, hi) - lo);
+                    }
+                    last = end;
+                }
+                DeltaElement::Insert(ref n) => {
+                    if lo <= last && (last < hi || hi == self.base_len) {
+                        push_insert(&mut els, n.clone());
+                    }
+                }
+            }
+        }
+        Delta { els: els, base_len: hi - lo }
+    }
+
+    /// For each range of the base document copied into the new document,
+    /// return the range paired with the offset in the new document where it
+    /// starts. Everything outside these ranges is deleted.
+    pub fn copied_mapping(&self) -> Vec<(Interval, usize)> {
+        let mut result = Vec::new();
+        let mut x = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    result.push((Interval::new_closed_open(beg, end), x));
+                    x += end - beg;
+                }
+                DeltaElement::Insert(ref n) => x += n.len(),
+            }
+        }
+        result
+    }
+
+    /// The runs of the base document copied into the new document that are
+    /// at least `min_len` long, in the form of `copied_mapping`, for example
+    /// so a transfer protocol can send references to large unchanged runs
+    /// instead of their text. Adjacent copies are merged into one run first.
+    pub fn large_copies(&self, min_len: usize) -> Vec<(Interval, usize)> {
+        let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+        for (iv, new_start) in self.copied_mapping() {
+            let (beg, end) = iv.start_end();
+            if let Some(last) = runs.last_mut() {
+                if last.1 == beg && last.2 + (last.1 - last.0) == new_start {
+                    last.1 = end;
+                    continue;
+                }
+            }
+            runs.push((beg, end, new_start));
+        }
+        runs.into_iter()
+            .filter(|&(beg, end, _)| end > beg && end - beg >= min_len)
+            .map(|(beg, end, new_start)| (Interval::new_closed_open(beg, end), new_start))
+            .collect()
+    }
+
+    /// Tag each span of the new document with its origin: either copied
+    /// from the old document, or inserted. The spans are in order, and tile
+    /// the whole new document without gaps or overlaps.
+    pub fn annotate(&self) -> Vec<(Interval, Annotation)> {
+        let mut result = Vec::new();
+        let mut x = 0;
+        for elem in &self.els {
+            let (len, annotation) = match *elem {
+                DeltaElement::Copy(beg, end) => (end - beg, Annotation::Unchanged { old_start: beg }),
+                DeltaElement::Insert(ref n) => (n.len(), Annotation::Inserted),
+            };
+            if len > 0 {
+                result.push((Interval::new_closed_open(x, x + len), annotation));
+                x += len;
+            }
+        }
+        result
+    }
+
+    /// Put the delta in canonical form: contiguous copies and adjacent
+    /// inserts are merged, and empty elements are dropped. Deltas that have
+    /// the same effect on every document have the same canonical form.
+    pub fn coalesce(self) -> Delta<N> {
+        let mut els = Vec::with_capacity(self.els.len());
+        for elem in self.els {
+            match elem {
+                DeltaElement::Copy(beg, end) => if end > beg {
+                    push_copy(&mut els, beg, end);
+                },
+                DeltaElement::Insert(n) => if n.len() > 0 {
+                    push_insert(&mut els, n);
+                },
+            }
+        }
+        Delta { els: els, base_len: self.base_len }
+    }
+
+    /// Compose the delta with `other`, a delta whose base is the result of
+    /// this one, into a single delta from this delta's base to `other`'s
+    /// result. In other words, `a.compose(&b).apply(s) == b.apply(&a.apply(s))`.
+    pub fn compose(&self, other: &Delta<N>) -> Delta<N> {
+        assert_eq!(self.new_document_len(), other.base_len, "composed Deltas must have compatible lengths");
+        // The start of each of our elements, in our result's coordinates.
+        let mut starts = Vec::with_capacity(self.els.len());
+        let mut x = 0;
+        for elem in &self.els {
+            starts.push(x);
+            x += match *elem {
+                DeltaElement::Copy(beg, end) => end - beg,
+                DeltaElement::Insert(ref n) => n.len(),
+            };
+        }
+        let mut els = Vec::new();
+        for elem in &other.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    let mut i = match starts.binary_search(&beg) {
+                        Ok(i) => i,
+                        Err(i) => i - 1,
+                    };
+                    while i < self.els.len() && starts[i] < end {
+                        let b = max(beg, starts[i]) - starts[i];
+                        match self.els[i] {
+                            DeltaElement::Copy(cb, ce) => {
+                                let e = min(end - starts[i], ce - cb);
+                                if e > b {
+                                    push_copy(&mut els, cb + b, cb + e);
+                                }
+                            }
+                            DeltaElement::Insert(ref n) => {
+                                let e = min(end - starts[i], n.len());
+                                if e > b {
+                                    els.push(DeltaElement::Insert(n.subseq(Interval::new_closed_open(b, e))));
+                                }
+                            }
+                        }
+                        i += 1;
+                    }
+                }
+                DeltaElement::Insert(ref n) => els.push(DeltaElement::Insert(n.clone())),
+            }
+        }
+        Delta { els: els, base_len: self.base_len }
+    }
+
+    /// Compose a chain of deltas, each applying to the result of the one
+    /// before, into a single delta. `base_len` is the length of the document
+    /// the first delta applies to; an empty chain composes to the identity.
+    pub fn compose_all(deltas: &[Delta<N>], base_len: usize) -> Delta<N> {
+        let identity = Delta {
+            els: if base_len > 0 { vec![DeltaElement::Copy(0, base_len)] } else { Vec::new() },
+            base_len: base_len,
+        };
+        deltas.iter().fold(identity, |acc, delta| acc.compose(delta))
     }
 
     /// Returns the length of the new document. In other words, the length of
@@ -224,6 +941,17 @@ impl<N: NodeInfo> Delta<N> {
         Delta::total_element_len(self.els.as_slice())
     }
 
+    /// The length of each element of the delta in order: the length of the
+    /// copied range or of the inserted rope. This approximates the cost of
+    /// applying each element, for splitting the work into chunks of similar
+    /// cost. The costs sum to `new_document_len`.
+    pub fn element_costs(&self) -> Vec<usize> {
+        self.els.iter().map(|el| match *el {
+            DeltaElement::Copy(beg, end) => end - beg,
+            DeltaElement::Insert(ref n) => n.len(),
+        }).collect()
+    }
+
     fn total_element_len(els: &[DeltaElement<N>]) -> usize {
         els.iter().fold(0, |sum, el|
             sum + match *el {
@@ -234,6 +962,28 @@ impl<N: NodeInfo> Delta<N> {
     }
 }
 
+// Append a copy to the elements of a delta under construction, extending the
+// previous copy if they are contiguous.
+fn push_copy<N: NodeInfo>(els: &mut Vec<DeltaElement<N>>, beg: usize, end: usize) {
+    if let Some(&mut DeltaElement::Copy(_, ref mut le)) = els.last_mut() {
+        if *le == beg {
+            *le = end;
+            return;
+        }
+    }
+    els.push(DeltaElement::Copy(beg, end));
+}
+
+// Append an insert to the elements of a delta under construction, merging it
+// into the previous insert if there is one.
+fn push_insert<N: NodeInfo>(els: &mut Vec<DeltaElement<N>>, n: Node<N>) {
+    if let Some(&mut DeltaElement::Insert(ref mut last)) = els.last_mut() {
+        *last = Node::concat(last.clone(), n);
+        return;
+    }
+    els.push(DeltaElement::Insert(n));
+}
+
 impl<N: NodeInfo> fmt::Debug for Delta<N> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         try!(write!(f, "Delta("));
@@ -247,12 +997,766 @@ impl<N: NodeInfo> fmt::Debug for Delta<N> {
                 }
             }
         }
-        try!(write!(f, ")"));
-        Ok(())
+        try!(write!(f, ")"));
+        Ok(())
+    }
+}
+
+// Document hashes are polynomial: byte `b` at offset `i` contributes
+// `(b + 1) * HASH_BASE^i`, so that the hash of a range moved by an edit can
+// be adjusted by a power of `HASH_BASE` rather than recomputed. The modulus
+// is prime and below 2^32, so products fit in a `u64`.
+const HASH_MODULUS: u64 = 4_294_967_291;
+const HASH_BASE: u64 = 1_000_003;
+
+fn hash_pow(mut base: u64, mut exp: u64) -> u64 {
+    let mut result = 1;
+    while exp > 0 {
+        if exp & 1 != 0 {
+            result = result * base % HASH_MODULUS;
+        }
+        base = base * base % HASH_MODULUS;
+        exp >>= 1;
+    }
+    result
+}
+
+// The factor that moves the hash of a range from offset `from` to `to`.
+fn hash_shift(from: usize, to: usize) -> u64 {
+    let inverse = hash_pow(HASH_BASE, HASH_MODULUS - 2);
+    hash_pow(HASH_BASE, to as u64) * hash_pow(inverse, from as u64) % HASH_MODULUS
+}
+
+// Add the hash of `bytes` to `*result`, where `*weight` is the weight of the
+// first byte, and advance `*weight` past them.
+fn hash_bytes(bytes: &[u8], result: &mut u64, weight: &mut u64) {
+    for &b in bytes {
+        *result = (*result + (b as u64 + 1) * *weight) % HASH_MODULUS;
+        *weight = *weight * HASH_BASE % HASH_MODULUS;
+    }
+}
+
+// The hash of the range from `beg` to `end` of `n`, as if it were at
+// offset `at`. The tree's offsets must count the bytes of its leaves.
+fn range_hash<N: NodeInfo>(n: &Node<N>, beg: usize, end: usize, at: usize) -> u64 where N::L: AsRef<[u8]> {
+    let mut result = 0;
+    let mut weight = hash_pow(HASH_BASE, at as u64);
+    let mut cursor = Cursor::new(n, beg);
+    let mut offset = beg;
+    while offset < end {
+        if let Some((leaf, offset_in_leaf)) = cursor.get_leaf() {
+            let bytes = &leaf.as_ref()[offset_in_leaf..];
+            let len = min(bytes.len(), end - offset);
+            hash_bytes(&bytes[..len], &mut result, &mut weight);
+            offset += len;
+        }
+        if cursor.next_leaf().is_none() {
+            break;
+        }
+    }
+    result
+}
+
+// Add the hash of the inserts in `els` to `*result`, at their offsets in the
+// new document, where `*offset` is the offset of the first element, and
+// advance `*offset` past them.
+fn hash_inserts<N: NodeInfo>(els: &[DeltaElement<N>], result: &mut u64, offset: &mut usize)
+        where N::L: AsRef<[u8]> {
+    for elem in els {
+        match *elem {
+            DeltaElement::Copy(beg, end) => *offset += end - beg,
+            DeltaElement::Insert(ref n) => {
+                *result = (*result + range_hash(n, 0, n.len(), *offset)) % HASH_MODULUS;
+                *offset += n.len();
+            }
+        }
+    }
+}
+
+impl<N: NodeInfo> Delta<N> where N::L: AsRef<[u8]> {
+    /// The hash of a whole document, in the form that `new_doc_hash`
+    /// updates. The algorithm is fixed, so hashes can be compared between
+    /// processes, for example to check that two peers agree.
+    pub fn doc_hash(text: &Node<N>) -> u64 {
+        let mut result = 0;
+        let mut weight = 1;
+        let mut cursor = Cursor::new(text, 0);
+        while let Some((leaf, _)) = cursor.get_leaf() {
+            hash_bytes(leaf.as_ref(), &mut result, &mut weight);
+            if cursor.next_leaf().is_none() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Compute `Delta::doc_hash` of the result of applying the delta to
+    /// `base`, given `base_hash`, the hash of `base`. The inserts are hashed,
+    /// as are all the ranges of the base except the largest copied one,
+    /// whose hash is instead derived from `base_hash`; so when copies
+    /// dominate, most of the document is not rehashed.
+    ///
+    /// Offsets in `doc_hash` are byte offsets, so this assumes the tree's
+    /// offsets count the bytes of its leaves, as `Rope`'s do.
+    pub fn new_doc_hash(&self, base_hash: u64, base: &Node<N>) -> u64 {
+        let mut copies = Vec::new();
+        let mut offset = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    copies.push((beg, end, offset));
+                    offset += end - beg;
+                }
+                DeltaElement::Insert(ref n) => offset += n.len(),
+            }
+        }
+        let mut result = 0;
+        hash_inserts(&self.els, &mut result, &mut 0);
+        let largest = match (0..copies.len()).max_by_key(|&i| copies[i].1 - copies[i].0) {
+            Some(largest) => largest,
+            None => return result,
+        };
+        // The hash of the base outside the largest copy, at base offsets.
+        let mut rest = 0;
+        let mut last = 0;
+        for (i, &(beg, end, new_offset)) in copies.iter().enumerate() {
+            rest = (rest + range_hash(base, last, beg, last)) % HASH_MODULUS;
+            last = end;
+            if i != largest {
+                let h = range_hash(base, beg, end, beg);
+                rest = (rest + h) % HASH_MODULUS;
+                result = (result + h * hash_shift(beg, new_offset)) % HASH_MODULUS;
+            }
+        }
+        rest = (rest + range_hash(base, last, self.base_len, last)) % HASH_MODULUS;
+        let (beg, _, new_offset) = copies[largest];
+        let largest_hash = (base_hash % HASH_MODULUS + HASH_MODULUS - rest) % HASH_MODULUS;
+        (result + largest_hash * hash_shift(beg, new_offset)) % HASH_MODULUS
+    }
+
+    /// Determine whether `other` extends this delta: whether it makes the
+    /// same changes, and possibly further ones, but none before the end of
+    /// this delta's last change. Then `other` is this delta followed by a
+    /// delta touching only the rest of the document, so a client that has
+    /// already applied this delta can tell that a retried `other` overlaps
+    /// it.
+    pub fn is_prefix_of(&self, other: &Delta<N>) -> bool {
+        if self.base_len != other.base_len {
+            return false;
+        }
+        let mine = self.replacements();
+        let theirs = other.replacements();
+        let (last, init) = match mine.split_last() {
+            Some(split) => split,
+            None => return true,
+        };
+        if theirs.len() < mine.len() || &theirs[..init.len()] != init {
+            return false;
+        }
+        let extended = &theirs[init.len()];
+        extended.0 == last.0 && extended.1 >= last.1 && extended.2.starts_with(&last.2)
+    }
+
+    // The changes the delta makes, as replacements of non-overlapping base
+    // ranges by inserted bytes, in order, like `to_text_edits`.
+    fn replacements(&self) -> Vec<(usize, usize, Vec<u8>)> {
+        let mut result = Vec::new();
+        let mut last = 0;
+        let mut inserted = Vec::new();
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > last || !inserted.is_empty() {
+                        result.push((last, beg, mem::replace(&mut inserted, Vec::new())));
+                    }
+                    last = end;
+                }
+                DeltaElement::Insert(ref n) => {
+                    let mut cursor = Cursor::new(n, 0);
+                    while let Some((leaf, _)) = cursor.get_leaf() {
+                        inserted.extend_from_slice(leaf.as_ref());
+                        if cursor.next_leaf().is_none() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        if self.base_len > last || !inserted.is_empty() {
+            result.push((last, self.base_len, inserted));
+        }
+        result
+    }
+}
+
+impl Delta<RopeInfo> {
+    /// Compute a delta from `base` to `target`. This finds the common prefix
+    /// and suffix of the two and replaces everything between them, which is
+    /// not a minimal diff, but is cheap and exact.
+    pub fn from_diff(base: &Rope, target: &Rope) -> Delta<RopeInfo> {
+        let base_str = String::from(base);
+        let target_str = String::from(target);
+        let (b, t) = (base_str.as_bytes(), target_str.as_bytes());
+        let mut prefix = b.iter().zip(t.iter()).take_while(|&(x, y)| x == y).count();
+        while !base_str.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let max_suffix = min(b.len(), t.len()) - prefix;
+        let mut suffix = b.iter().rev().zip(t.iter().rev()).take(max_suffix)
+            .take_while(|&(x, y)| x == y).count();
+        while !base_str.is_char_boundary(b.len() - suffix) {
+            suffix -= 1;
+        }
+        let iv = Interval::new_closed_open(prefix, b.len() - suffix);
+        let new = target.subseq(Interval::new_closed_open(prefix, t.len() - suffix));
+        Delta::simple_edit(iv, new, b.len())
+    }
+
+    /// Apply the delta to a plain string, writing the result into `out`. The
+    /// previous contents of `out` are discarded, but its allocation is
+    /// reused, so calling this repeatedly with the same buffer avoids
+    /// allocating on every edit.
+    ///
+    /// Panics if `base` is not of the length the delta was built for, or if a
+    /// copied range does not fall on char boundaries of `base`.
+    pub fn apply_to_string_buf(&self, base: &str, out: &mut String) {
+        assert_eq!(base.len(), self.base_len, "must apply Delta to string of correct length");
+        out.clear();
+        out.reserve(self.new_document_len());
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    assert!(base.is_char_boundary(beg) && base.is_char_boundary(end),
+                        "Delta copy range [{}, {}) not on char boundaries", beg, end);
+                    out.push_str(&base[beg..end]);
+                }
+                DeltaElement::Insert(ref n) => {
+                    for chunk in n.iter_chunks(0, n.len()) {
+                        out.push_str(chunk);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rewrite the line endings in inserted text to the `to` convention,
+    /// leaving text copied from `base` untouched. A `\r` at the end of an
+    /// insert that is followed by a `\n` copied from `base` (or the reverse)
+    /// is treated as one `\r\n` line ending. Adjacent inserts are merged.
+    pub fn normalize_line_endings(&self, base: &Rope, to: LineEnding) -> Delta<RopeInfo> {
+        let mut els = Vec::new();
+        let mut pending = String::new();
+        let mut prev_byte = None;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    let next_byte = if beg < end { Some(base.byte_at(beg)) } else { None };
+                    if !pending.is_empty() {
+                        let text = normalize_str_line_endings(&pending, prev_byte, next_byte, to);
+                        els.push(DeltaElement::Insert(Rope::from(text)));
+                        pending.clear();
+                    }
+                    els.push(DeltaElement::Copy(beg, end));
+                    if beg < end {
+                        prev_byte = Some(base.byte_at(end - 1));
+                    }
+                }
+                DeltaElement::Insert(ref n) => {
+                    for chunk in n.iter_chunks(0, n.len()) {
+                        pending.push_str(chunk);
+                    }
+                }
+            }
+        }
+        if !pending.is_empty() {
+            let text = normalize_str_line_endings(&pending, prev_byte, None, to);
+            els.push(DeltaElement::Insert(Rope::from(text)));
+        }
+        Delta { els: els, base_len: self.base_len }
+    }
+
+    /// Encode the delta in a compact binary format, suitable for persisting
+    /// large undo logs. The format is a version byte, the base length, then
+    /// a sequence of tagged elements: a copy is encoded as its offset from
+    /// the end of the previous copy followed by its length, and an insert as
+    /// a length-prefixed UTF-8 string. All integers are LEB128 varints.
+    ///
+    /// Copies must be in ascending order, as is the case for all deltas
+    /// built by `Builder` or `synthesize`.
+    pub fn encode_binary(&self) -> Vec<u8> {
+        let mut result = vec![BINARY_VERSION];
+        write_varint(&mut result, self.base_len);
+        let mut last = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    debug_assert!(beg >= last, "encode_binary requires ascending copies");
+                    result.push(BINARY_TAG_COPY);
+                    write_varint(&mut result, beg - last);
+                    write_varint(&mut result, end - beg);
+                    last = end;
+                }
+                DeltaElement::Insert(ref n) => {
+                    result.push(BINARY_TAG_INSERT);
+                    write_varint(&mut result, n.len());
+                    for chunk in n.iter_chunks(0, n.len()) {
+                        result.extend_from_slice(chunk.as_bytes());
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Decode a delta from the format produced by `encode_binary`. Malformed
+    /// input, including copies out of range of the base, is reported as an
+    /// error rather than a panic.
+    pub fn decode_binary(bytes: &[u8]) -> Result<Delta<RopeInfo>, DecodeError> {
+        let mut bytes = bytes;
+        match read_byte(&mut bytes)? {
+            BINARY_VERSION => (),
+            version => return Err(DecodeError::UnsupportedVersion(version)),
+        }
+        let base_len = read_varint(&mut bytes)?;
+        let mut els = Vec::new();
+        let mut last: usize = 0;
+        while !bytes.is_empty() {
+            match read_byte(&mut bytes)? {
+                BINARY_TAG_COPY => {
+                    let offset = read_varint(&mut bytes)?;
+                    let len = read_varint(&mut bytes)?;
+                    let beg = last.checked_add(offset);
+                    let end = beg.and_then(|beg| beg.checked_add(len));
+                    match (beg, end) {
+                        (Some(beg), Some(end)) if end <= base_len => {
+                            els.push(DeltaElement::Copy(beg, end));
+                            last = end;
+                        }
+                        _ => return Err(DecodeError::InvalidRange),
+                    }
+                }
+                BINARY_TAG_INSERT => {
+                    let len = read_varint(&mut bytes)?;
+                    if len > bytes.len() {
+                        return Err(DecodeError::UnexpectedEof);
+                    }
+                    let (text, rest) = bytes.split_at(len);
+                    let text = str::from_utf8(text).map_err(|_| DecodeError::InvalidUtf8)?;
+                    els.push(DeltaElement::Insert(Rope::from(text)));
+                    bytes = rest;
+                }
+                tag => return Err(DecodeError::InvalidTag(tag)),
+            }
+        }
+        Ok(Delta { els: els, base_len: base_len })
+    }
+
+    /// Express the delta as a list of replacements of ranges of the old
+    /// document. Each maximal run of deletions and insertions between copied
+    /// regions becomes one edit. The edits are sorted and non-overlapping, so
+    /// applying them from last to first reproduces `self.apply(base)`.
+    pub fn to_text_edits(&self, base: &Rope) -> Vec<TextEdit> {
+        debug_assert_eq!(base.len(), self.base_len, "must use the base Rope the Delta applies to");
+        let mut result = Vec::new();
+        let mut new_text = String::new();
+        let mut last = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > last || !new_text.is_empty() {
+                        result.push(TextEdit { start: last, end: beg, new_text: new_text });
+                        new_text = String::new();
+                    }
+                    last = end;
+                }
+                DeltaElement::Insert(ref n) => {
+                    for chunk in n.iter_chunks(0, n.len()) {
+                        new_text.push_str(chunk);
+                    }
+                }
+            }
+        }
+        if self.base_len > last || !new_text.is_empty() {
+            result.push(TextEdit { start: last, end: self.base_len, new_text: new_text });
+        }
+        result
+    }
+
+    /// Like `to_text_edits`, but edits separated by a copied region of at
+    /// most `max_gap` bytes are merged into one, whose `new_text` includes
+    /// the copied text between them. With a `max_gap` of 0, this is the same
+    /// as `to_text_edits`.
+    pub fn to_text_edits_merged(&self, base: &Rope, max_gap: usize) -> Vec<TextEdit> {
+        let mut result: Vec<TextEdit> = Vec::new();
+        for edit in self.to_text_edits(base) {
+            if let Some(last) = result.last_mut() {
+                if edit.start - last.end <= max_gap {
+                    for chunk in base.iter_chunks(last.end, edit.start) {
+                        last.new_text.push_str(chunk);
+                    }
+                    last.new_text.push_str(&edit.new_text);
+                    last.end = edit.end;
+                    continue;
+                }
+            }
+            result.push(edit);
+        }
+        result
+    }
+
+    /// Decompose the delta into single-character operations, for example to
+    /// replay it as an animation. Applying the operations in order to `base`
+    /// reproduces `self.apply(base)`. The edits of `to_text_edits` are taken
+    /// in document order, and each is expressed as deleting the replaced
+    /// characters and then typing the new text.
+    pub fn to_micro_ops(&self, base: &Rope) -> Vec<MicroOp> {
+        let mut result = Vec::new();
+        // The offset in the edited document minus the offset in the base.
+        let mut shift = 0isize;
+        for edit in self.to_text_edits(base) {
+            let mut at = (edit.start as isize + shift) as usize;
+            for chunk in base.iter_chunks(edit.start, edit.end) {
+                result.extend(chunk.chars().map(|_| MicroOp::DeleteChar { at: at }));
+            }
+            for ch in edit.new_text.chars() {
+                result.push(MicroOp::InsertChar { at: at, ch: ch });
+                at += ch.len_utf8();
+            }
+            shift += edit.new_text.len() as isize - (edit.end - edit.start) as isize;
+        }
+        result
+    }
+
+    /// Determine whether the delta only inserts and deletes whitespace, by
+    /// examining both the inserted text and the deleted ranges of `base`.
+    /// A delta that changes nothing is trivially whitespace-only.
+    pub fn is_whitespace_only(&self, base: &Rope) -> bool {
+        fn all_whitespace<'a, I: Iterator<Item=&'a str>>(mut chunks: I) -> bool {
+            chunks.all(|chunk| chunk.chars().all(char::is_whitespace))
+        }
+        let mut last = 0;
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > last && !all_whitespace(base.iter_chunks(last, beg)) {
+                        return false;
+                    }
+                    last = end;
+                }
+                DeltaElement::Insert(ref n) => {
+                    if !all_whitespace(n.iter_chunks(0, n.len())) {
+                        return false;
+                    }
+                }
+            }
+        }
+        last >= self.base_len || all_whitespace(base.iter_chunks(last, self.base_len))
+    }
+
+    /// Split each insert longer than `max_len` bytes into a sequence of
+    /// inserts of about `max_len` bytes each. Splits are only made at
+    /// grapheme boundaries, as found by `Rope::next_grapheme_offset`, so a
+    /// chunk may exceed `max_len` by up to one grapheme.
+    pub fn split_large_inserts(&self, max_len: usize) -> Delta<RopeInfo> {
+        assert!(max_len > 0, "split_large_inserts requires max_len > 0");
+        let mut els = Vec::with_capacity(self.els.len());
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Insert(ref n) if n.len() > max_len => {
+                    let mut beg = 0;
+                    let mut offset = 0;
+                    while let Some(next) = n.next_grapheme_offset(offset) {
+                        offset = next;
+                        if offset < n.len() && offset - beg >= max_len {
+                            els.push(DeltaElement::Insert(n.subseq(Interval::new_closed_open(beg, offset))));
+                            beg = offset;
+                        }
+                    }
+                    els.push(DeltaElement::Insert(n.subseq(Interval::new_closed_open(beg, n.len()))));
+                }
+                _ => els.push(elem.clone()),
+            }
+        }
+        Delta { els: els, base_len: self.base_len }
+    }
+
+    /// Determine whether every inserted rope is internally consistent: its
+    /// cached lengths and line counts agree with its text. This guards
+    /// against malformed deltas received from other peers.
+    pub fn has_well_formed_inserts(&self) -> bool {
+        self.els.iter().all(|elem| match *elem {
+            DeltaElement::Copy(..) => true,
+            DeltaElement::Insert(ref n) => {
+                n.is_well_formed() && n.measure::<LinesMetric>() ==
+                    n.iter_chunks(0, n.len()).fold(0, |lines, chunk| lines + chunk.matches('\n').count())
+            }
+        })
+    }
+
+    /// Return the concatenation, in order, of all the inserted text.
+    pub fn all_inserted(&self) -> String {
+        self.inserted_slices().collect()
+    }
+
+    /// Return an iterator over the content of each insert, in order. An
+    /// insert held in a single leaf is borrowed rather than copied.
+    pub fn inserted_slices(&self) -> InsertedSlices {
+        InsertedSlices {
+            els: self.els.iter(),
+        }
+    }
+
+    /// Compute the net change in the number of whitespace-delimited words
+    /// caused by applying the delta to `base`, without counting the whole
+    /// document.
+    ///
+    /// Each changed region is widened to word boundaries in `base`: leftward
+    /// while the preceding character is not whitespace, and rightward while
+    /// the following character is not whitespace. The words in the widened
+    /// region are compared against the words in its replacement, so words
+    /// split or joined at the edges of an edit are counted correctly. Regions
+    /// whose widened extents meet are treated as one.
+    pub fn word_count_delta(&self, base: &Rope) -> isize {
+        fn count_words(s: &str) -> isize {
+            s.split_whitespace().count() as isize
+        }
+        fn word_start(base: &Rope, ix: usize) -> usize {
+            let mut cursor = Cursor::new(base, ix);
+            loop {
+                let pos = cursor.pos();
+                match cursor.prev_codepoint() {
+                    Some(c) if !c.is_whitespace() => (),
+                    _ => return pos,
+                }
+            }
+        }
+        fn word_end(base: &Rope, ix: usize) -> usize {
+            let mut cursor = Cursor::new(base, ix);
+            loop {
+                let pos = cursor.pos();
+                match cursor.next_codepoint() {
+                    Some(c) if !c.is_whitespace() => (),
+                    _ => return pos,
+                }
+            }
+        }
+        // Net change for a widened region starting at `start`, whose last
+        // edit ends at `last_end`, with `new_text` replacing start..last_end.
+        fn region_delta(base: &Rope, start: usize, last_end: usize, mut new_text: String) -> isize {
+            let end = word_end(base, last_end);
+            new_text.push_str(&base.slice_to_string(last_end, end));
+            count_words(&new_text) - count_words(&base.slice_to_string(start, end))
+        }
+
+        let mut result = 0;
+        let mut region: Option<(usize, usize, String)> = None;
+        for edit in self.to_text_edits(base) {
+            let start = word_start(base, edit.start);
+            region = match region.take() {
+                Some((region_start, last_end, mut new_text)) if start <= word_end(base, last_end) => {
+                    new_text.push_str(&base.slice_to_string(last_end, edit.start));
+                    new_text.push_str(&edit.new_text);
+                    Some((region_start, edit.end, new_text))
+                }
+                prev => {
+                    if let Some((region_start, last_end, new_text)) = prev {
+                        result += region_delta(base, region_start, last_end, new_text);
+                    }
+                    let mut new_text = base.slice_to_string(start, edit.start);
+                    new_text.push_str(&edit.new_text);
+                    Some((start, edit.end, new_text))
+                }
+            };
+        }
+        if let Some((region_start, last_end, new_text)) = region {
+            result += region_delta(base, region_start, last_end, new_text);
+        }
+        result
+    }
+}
+
+const BINARY_VERSION: u8 = 1;
+const BINARY_TAG_COPY: u8 = 0;
+const BINARY_TAG_INSERT: u8 = 1;
+
+/// An error that occurred while decoding a binary-encoded `Delta`.
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The format version is not one this code understands.
+    UnsupportedVersion(u8),
+    /// The input ended in the middle of a value.
+    UnexpectedEof,
+    /// A varint does not fit in a `usize`.
+    VarintOverflow,
+    /// An element had an unknown tag.
+    InvalidTag(u8),
+    /// A copied range is out of order or extends past the base document.
+    InvalidRange,
+    /// Inserted text is not valid UTF-8.
+    InvalidUtf8,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DecodeError::*;
+
+        match *self {
+            UnsupportedVersion(version) => write!(f, "Error: unsupported delta encoding version {}", version),
+            UnexpectedEof => write!(f, "Error: unexpected end of encoded delta"),
+            VarintOverflow => write!(f, "Error: varint overflow in encoded delta"),
+            InvalidTag(tag) => write!(f, "Error: invalid element tag {} in encoded delta", tag),
+            InvalidRange => write!(f, "Error: invalid copy range in encoded delta"),
+            InvalidUtf8 => write!(f, "Error: invalid UTF-8 in encoded delta"),
+        }
+    }
+}
+
+impl error::Error for DecodeError {
+    fn description(&self) -> &str {
+        use self::DecodeError::*;
+
+        match *self {
+            UnsupportedVersion(_) => "Unsupported delta encoding version",
+            UnexpectedEof => "Unexpected end of encoded delta",
+            VarintOverflow => "Varint overflow in encoded delta",
+            InvalidTag(_) => "Invalid element tag in encoded delta",
+            InvalidRange => "Invalid copy range in encoded delta",
+            InvalidUtf8 => "Invalid UTF-8 in encoded delta",
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_byte(bytes: &mut &[u8]) -> Result<u8, DecodeError> {
+    match bytes.split_first() {
+        Some((&b, rest)) => {
+            *bytes = rest;
+            Ok(b)
+        }
+        None => Err(DecodeError::UnexpectedEof),
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<usize, DecodeError> {
+    let mut result: usize = 0;
+    let mut shift = 0;
+    loop {
+        let b = read_byte(bytes)?;
+        let bits = (b & 0x7f) as usize;
+        if shift >= usize::max_value().count_ones() || (bits << shift) >> shift != bits {
+            return Err(DecodeError::VarintOverflow);
+        }
+        result |= bits << shift;
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+// Rewrite the line endings of `s` to `to`. `prev` and `next` are the bytes
+// immediately surrounding `s` in the document, if any.
+fn normalize_str_line_endings(s: &str, prev: Option<u8>, next: Option<u8>, to: LineEnding) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    let mut first = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' if chars.peek() == Some(&'\n') => {
+                chars.next();
+                result.push_str(to.as_str());
+            }
+            '\r' if chars.peek().is_none() && next == Some(b'\n') => {
+                // the copied '\n' that follows completes the line ending
+                if to == LineEnding::CrLf {
+                    result.push('\r');
+                }
+            }
+            '\n' if first && prev == Some(b'\r') => {
+                // the copied '\r' that precedes already makes this a '\r\n'
+                result.push('\n');
+            }
+            '\n' => result.push_str(to.as_str()),
+            c => result.push(c),
+        }
+        first = false;
+    }
+    result
+}
+
+/// An error that occurred while merging insert-only deltas with
+/// `InsertDelta::merge_inserts`.
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+    /// A delta applies to a document of a different length.
+    BaseLenMismatch,
+    /// Two of the deltas insert at the given base offset, so the order of
+    /// their insertions is ambiguous.
+    SamePosition(usize),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MergeError::*;
+
+        match *self {
+            BaseLenMismatch => write!(f, "Error: merged deltas have different base lengths"),
+            SamePosition(offset) => write!(f, "Error: merged deltas both insert at offset {}", offset),
+        }
+    }
+}
+
+impl error::Error for MergeError {
+    fn description(&self) -> &str {
+        use self::MergeError::*;
+
+        match *self {
+            BaseLenMismatch => "Merged deltas have different base lengths",
+            SamePosition(_) => "Merged deltas insert at the same offset",
+        }
     }
 }
 
 impl<N: NodeInfo> InsertDelta<N> {
+    /// Merge several insert-only deltas over the same document, such as the
+    /// insertions at each of several cursors, into one that makes all their
+    /// insertions. Insertions are ordered by base offset. If two different
+    /// deltas insert at the same offset there is no right order for them, so
+    /// this returns `MergeError::SamePosition`; insertions at one offset from
+    /// a single delta keep their order.
+    pub fn merge_inserts(base_len: usize, deltas: &[InsertDelta<N>]) -> Result<InsertDelta<N>, MergeError> {
+        // (offset, index of the delta, contents) for every insertion
+        let mut inserts = Vec::new();
+        for (i, delta) in deltas.iter().enumerate() {
+            if delta.base_len != base_len {
+                return Err(MergeError::BaseLenMismatch);
+            }
+            let mut last = 0;
+            for elem in &delta.els {
+                match *elem {
+                    DeltaElement::Copy(_, end) => last = end,
+                    DeltaElement::Insert(ref n) => inserts.push((last, i, n.clone())),
+                }
+            }
+        }
+        // A stable sort keeps each delta's insertions at an offset in order.
+        inserts.sort_by(|a, b| a.0.cmp(&b.0));
+        for pair in inserts.windows(2) {
+            if pair[0].0 == pair[1].0 && pair[0].1 != pair[1].1 {
+                return Err(MergeError::SamePosition(pair[0].0));
+            }
+        }
+        let inserts = inserts.into_iter().map(|(offset, _, n)| (offset, n)).collect();
+        Ok(InsertDelta(Delta::from_subset_and_inserts(base_len, &Subset::default(), inserts)))
+    }
+
     /// Do a coordinate transformation on an insert-only delta. The `after` parameter
     /// controls whether the insertions in `self` come after those specific in the
     /// coordinate transform.
@@ -260,6 +1764,15 @@ impl<N: NodeInfo> InsertDelta<N> {
     // TODO: write accurate equations
     // TODO: can we infer l from the other inputs?
     pub fn transform_expand(&self, xform: &Subset, l: usize, after: bool) -> InsertDelta<N> {
+        let bias = if after { InsertBias::Right } else { InsertBias::Left };
+        self.transform_expand_biased(xform, l, bias)
+    }
+
+    /// Do a coordinate transformation on an insert-only delta, with `bias`
+    /// choosing whether the insertions in `self` land before or after those
+    /// at the same location in the coordinate transform.
+    pub fn transform_expand_biased(&self, xform: &Subset, l: usize, bias: InsertBias) -> InsertDelta<N> {
+        let after = bias == InsertBias::Right;
         let cur_els = &self.0.els;
         let mut els = Vec::new();
         let mut x = 0;  // coordinate within self
@@ -370,6 +1883,29 @@ impl<N: NodeInfo> Deref for InsertDelta<N> {
     }
 }
 
+/// The iterator returned by `Delta::inserted_slices`.
+pub struct InsertedSlices<'a> {
+    els: slice::Iter<'a, DeltaElement<RopeInfo>>,
+}
+
+impl<'a> Iterator for InsertedSlices<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        for elem in &mut self.els {
+            if let DeltaElement::Insert(ref n) = *elem {
+                if let Some((leaf, 0)) = Cursor::new(n, 0).get_leaf() {
+                    if leaf.len() == n.len() {
+                        return Some(Cow::Borrowed(leaf));
+                    }
+                }
+                return Some(Cow::Owned(String::from(n)));
+            }
+        }
+        None
+    }
+}
+
 /// A mapping from coordinates in the source sequence to coordinates in the sequence after
 /// the delta is applied.
 
@@ -377,21 +1913,33 @@ impl<N: NodeInfo> Deref for InsertDelta<N> {
 // like Delta but missing the strings, or perhaps the two subsets it's synthesized from.
 pub struct Transformer<'a, N: NodeInfo + 'a> {
     delta: &'a Delta<N>,
+    caret_biases: &'a [(usize, InsertBias)],
 }
 
 impl<'a, N: NodeInfo + 'a> Transformer<'a, N> {
     /// Create a new transformer from a delta.
     pub fn new(delta: &'a Delta<N>) -> Self {
+        Transformer::with_caret_biases(delta, &[])
+    }
+
+    /// Create a new transformer from a delta and the caret biases of its
+    /// inserts, as returned by `Builder::build_with_caret_biases`, for use by
+    /// `transform_caret`.
+    pub fn with_caret_biases(delta: &'a Delta<N>, caret_biases: &'a [(usize, InsertBias)]) -> Self {
         Transformer {
             delta: delta,
+            caret_biases: caret_biases,
         }
     }
 
     /// Transform a single coordinate. The `after` parameter indicates whether it
-    /// it should land before or after an inserted region.
+    /// it should land before or after an inserted region. The coordinate must
+    /// be at most the base length; see `checked_transform`.
 
     // TODO: implement a cursor so we're not scanning from the beginning every time.
     pub fn transform(&mut self, ix: usize, after: bool) -> usize {
+        debug_assert!(ix <= self.delta.base_len, "coordinate {} is past the end of the base ({})",
+            ix, self.delta.base_len);
         if ix == 0 && !after {
             return 0;
         }
@@ -415,6 +1963,29 @@ impl<'a, N: NodeInfo + 'a> Transformer<'a, N> {
         return result;
     }
 
+    /// Like `transform`, but if `after` is `None`, a coordinate at an insert
+    /// with a caret bias given to `with_caret_biases` lands as that bias
+    /// says, and any other coordinate lands after inserted text.
+    pub fn transform_caret(&mut self, ix: usize, after: Option<bool>) -> usize {
+        let after = after.unwrap_or_else(|| {
+            match self.caret_biases.iter().rev().find(|&&(offset, _)| offset == ix) {
+                Some(&(_, InsertBias::Left)) => false,
+                _ => true,
+            }
+        });
+        self.transform(ix, after)
+    }
+
+    /// Like `transform`, but returns `None` if the coordinate is past the end
+    /// of the base.
+    pub fn checked_transform(&mut self, ix: usize, after: bool) -> Option<usize> {
+        if ix > self.delta.base_len {
+            None
+        } else {
+            Some(self.transform(ix, after))
+        }
+    }
+
     /// Determine whether a given interval is untouched by the transformation.
     pub fn interval_untouched(&mut self, iv: Interval) -> bool {
         let mut last_was_ins = true;
@@ -452,6 +2023,14 @@ impl<'a, N: NodeInfo + 'a> Transformer<'a, N> {
 pub struct Builder<N: NodeInfo> {
     delta: Delta<N>,
     last_offset: usize,
+    // The non-empty intervals passed to `delete` (or `replace`), in order.
+    deleted: Vec<(usize, usize)>,
+    // The offsets and caret biases passed to `insert`, in order.
+    caret_biases: Vec<(usize, InsertBias)>,
+    // The number of elements hashed by `running_hash`, their combined hash,
+    // and the offset in the new document after them, so that each call only
+    // hashes what was added since the previous one.
+    hashed: Cell<(usize, u64, usize)>,
 }
 
 impl<N: NodeInfo> Builder<N> {
@@ -463,6 +2042,9 @@ impl<N: NodeInfo> Builder<N> {
                 base_len: base_len,
             },
             last_offset: 0,
+            deleted: Vec::new(),
+            caret_biases: Vec::new(),
+            hashed: Cell::new((0, 0, 0)),
         }
     }
 
@@ -473,6 +2055,9 @@ impl<N: NodeInfo> Builder<N> {
         if start > self.last_offset {
             self.delta.els.push(DeltaElement::Copy(self.last_offset, start));
         }
+        if end > start {
+            self.deleted.push((start, end));
+        }
         self.last_offset = end;
     }
 
@@ -483,6 +2068,16 @@ impl<N: NodeInfo> Builder<N> {
         self.delta.els.push(DeltaElement::Insert(rope));
     }
 
+    /// Inserts the rope at the given base offset, recording where a caret at
+    /// that offset should land relative to the new text: before it for
+    /// `Left`, after it for `Right`. The recorded biases are not part of the
+    /// `Delta`; see `build_with_caret_biases`. Panics if the offset is not
+    /// properly sorted.
+    pub fn insert(&mut self, offset: usize, rope: Node<N>, caret_bias: InsertBias) {
+        self.replace(Interval::new_closed_open(offset, offset), rope);
+        self.caret_biases.push((offset, caret_bias));
+    }
+
     /// Determines if delta would be a no-op transformation if built.
     pub fn is_empty(&self) -> bool {
         self.last_offset == 0 && self.delta.els.is_empty()
@@ -495,17 +2090,130 @@ impl<N: NodeInfo> Builder<N> {
         }
         self.delta
     }
+
+    /// Like `build`, but also returns the caret biases recorded by `insert`,
+    /// to pass to `Transformer::with_caret_biases`.
+    pub fn build_with_caret_biases(mut self) -> (Delta<N>, Vec<(usize, InsertBias)>) {
+        let caret_biases = mem::replace(&mut self.caret_biases, Vec::new());
+        (self.build(), caret_biases)
+    }
+
+    /// Builds the `Delta`, checking that every offset of the base is either
+    /// copied or was explicitly deleted. `build` fills in the copies between
+    /// and after the intervals it is given, so this can only fail if the
+    /// builder was driven into an inconsistent state; it is meant for code
+    /// that generates deltas programmatically, as a guard against a region
+    /// being dropped without a `delete` saying so.
+    pub fn expect_full_coverage(self) -> Result<Delta<N>, BuilderError> {
+        let deleted = self.deleted.clone();
+        let delta = self.build();
+        let mut covered: Vec<(usize, usize)> = delta.els.iter()
+            .filter_map(|el| match *el {
+                DeltaElement::Copy(beg, end) if end > beg => Some((beg, end)),
+                _ => None,
+            })
+            .chain(deleted)
+            .collect();
+        covered.sort();
+        let mut offset = 0;
+        for (beg, end) in covered {
+            if beg > offset {
+                return Err(BuilderError::Uncovered(offset, beg));
+            } else if beg < offset {
+                return Err(BuilderError::Overlap(beg));
+            }
+            offset = end;
+        }
+        if offset < delta.base_len {
+            return Err(BuilderError::Uncovered(offset, delta.base_len));
+        } else if offset > delta.base_len {
+            return Err(BuilderError::Overlap(delta.base_len));
+        }
+        Ok(delta)
+    }
+}
+
+/// An error found by `Builder::expect_full_coverage`.
+#[derive(Debug, PartialEq)]
+pub enum BuilderError {
+    /// The base range from the first to the second offset is neither copied
+    /// nor explicitly deleted.
+    Uncovered(usize, usize),
+    /// The base offset is covered more than once, or lies past the end of
+    /// the base.
+    Overlap(usize),
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BuilderError::*;
+
+        match *self {
+            Uncovered(start, end) => write!(f, "Error: base range {}..{} is neither copied nor deleted", start, end),
+            Overlap(offset) => write!(f, "Error: base offset {} is covered more than once", offset),
+        }
+    }
+}
+
+impl error::Error for BuilderError {
+    fn description(&self) -> &str {
+        use self::BuilderError::*;
+
+        match *self {
+            Uncovered(..) => "Base range is neither copied nor deleted",
+            Overlap(_) => "Base offset is covered more than once",
+        }
+    }
+}
+
+impl<N: NodeInfo> Builder<N> where N::L: AsRef<[u8]> {
+    /// The hash of the text inserted so far, at its offsets in the new
+    /// document, in the form of `Delta::doc_hash`. This is the part of the
+    /// new document's hash that `Delta::new_doc_hash` adds to the hash of
+    /// the copied ranges. The hash is rolled forward over the inserts added
+    /// since the previous call, so calling this after each edit costs only
+    /// the length of the new text. As with `new_doc_hash`, the tree's
+    /// offsets must count bytes.
+    pub fn running_hash(&self) -> u64 {
+        let (count, mut result, mut offset) = self.hashed.get();
+        hash_inserts(&self.delta.els[count..], &mut result, &mut offset);
+        self.hashed.set((self.delta.els.len(), result, offset));
+        result
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use rope::Rope;
-    use delta::{Delta};
+    use rope::{LineEnding, Rope, RopeInfo};
+    use delta::{Annotation, Builder, BuilderError, DecodeError, Delta, DeltaClass, DeltaElement, InsertBias, InsertDelta,
+        MergeError, MicroOp, RemapPolicy, TextEdit, Transformer};
     use interval::Interval;
+    use subset::{Subset, SubsetBuilder};
+    use std::borrow::Cow;
+    use std::cmp::min;
     use test_helpers::find_deletions;
 
     const TEST_STR: &'static str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 
+    fn build_delta_1() -> Delta<RopeInfo> {
+        let mut d_builder = Builder::new(TEST_STR.len());
+        d_builder.delete(Interval::new_closed_open(10, 36));
+        d_builder.replace(Interval::new_closed_open(39, 42), Rope::from("DEEF"));
+        d_builder.replace(Interval::new_closed_open(54, 54), Rope::from("999"));
+        d_builder.delete(Interval::new_closed_open(58, 61));
+        d_builder.build()
+    }
+
+    fn build_delta_2() -> Delta<RopeInfo> {
+        let mut d_builder = Builder::new(TEST_STR.len());
+        d_builder.replace(Interval::new_closed_open(1, 3), Rope::from("!"));
+        d_builder.delete(Interval::new_closed_open(10, 36));
+        d_builder.replace(Interval::new_closed_open(42, 45), Rope::from("GI"));
+        d_builder.replace(Interval::new_closed_open(54, 54), Rope::from("888"));
+        d_builder.replace(Interval::new_closed_open(59, 60), Rope::from("HI"));
+        d_builder.build()
+    }
+
     #[test]
     fn simple() {
         let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
@@ -513,6 +2221,455 @@ mod tests {
         assert_eq!(6, d.new_document_len());
     }
 
+    #[test]
+    fn apply_to_string_buf() {
+        let mut buf = String::new();
+        let mut text = String::from("hello world");
+        let deltas = vec![
+            Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11),
+            Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("\u{1F4A9} "), 6),
+            Delta::simple_edit(Interval::new_closed_open(5, 10), Rope::from(""), 11),
+        ];
+        for d in &deltas {
+            d.apply_to_string_buf(&text, &mut buf);
+            assert_eq!(d.apply_to_string(&text), buf);
+            text = buf.clone();
+        }
+        assert_eq!("\u{1F4A9} d", text);
+    }
+
+    #[test]
+    #[should_panic(expected = "char boundaries")]
+    fn apply_to_string_buf_bad_boundary() {
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 2), Rope::from("x"), 5);
+        d.apply_to_string_buf("\u{1F4A9}a", &mut String::new());
+    }
+
+    #[test]
+    fn classify() {
+        let d = Delta::simple_edit(Interval::new_closed_open(3, 3), Rope::from(""), 11);
+        assert_eq!(DeltaClass::Identity, d.classify());
+        let d = Delta::simple_edit(Interval::new_closed_open(3, 3), Rope::from("era"), 11);
+        assert_eq!(DeltaClass::PureInsert, d.classify());
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 3), Rope::from(""), 11);
+        assert_eq!(DeltaClass::PureDelete, d.classify());
+        let d = Delta::simple_edit(Interval::new_closed_open(9, 11), Rope::from(""), 11);
+        assert_eq!(DeltaClass::PureDelete, d.classify());
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        assert_eq!(DeltaClass::Replace, d.classify());
+    }
+
+    #[test]
+    fn apply_recording_deletions() {
+        let d = build_delta_1();
+        let (new, deleted) = d.apply_recording_deletions(&Rope::from(TEST_STR));
+        assert_eq!("0123456789abcDEEFghijklmnopqr999stuvz", String::from(new));
+        assert_eq!("ABCDEFGHIJKLMNOPQRSTUVWXYZdefwxy", String::from(deleted));
+    }
+
+    #[test]
+    fn normalize_line_endings() {
+        let base = Rope::from("x\ny");
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 1), Rope::from("a\r\nb"), 3);
+        let d2 = d.normalize_line_endings(&base, LineEnding::Lf);
+        assert_eq!("xa\nb\ny", d2.apply_to_string("x\ny"));
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 1), Rope::from("a\nb\r"), 3);
+        let d2 = d.normalize_line_endings(&base, LineEnding::CrLf);
+        assert_eq!("xa\r\nb\r\ny", d2.apply_to_string("x\ny"));
+        let d2 = d.normalize_line_endings(&base, LineEnding::Lf);
+        assert_eq!("xa\nb\ny", d2.apply_to_string("x\ny"));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(1, 3), Rope::from("!"));
+        builder.delete(Interval::new_closed_open(10, 36));
+        builder.replace(Interval::new_closed_open(42, 45), Rope::from("GI\u{1F4A9}"));
+        builder.replace(Interval::new_closed_open(54, 54), Rope::from("888"));
+        builder.replace(Interval::new_closed_open(59, 60), Rope::from("HI"));
+        let deltas = vec![
+            builder.build(),
+            Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from(""), TEST_STR.len()),
+            Delta::simple_edit(Interval::new_closed_open(0, 62), Rope::from(""), TEST_STR.len()),
+            Delta::simple_edit(Interval::new_closed_open(0, 62), Rope::from(TEST_STR.repeat(10)), TEST_STR.len()),
+        ];
+        for d in &deltas {
+            let decoded = Delta::decode_binary(&d.encode_binary()).unwrap();
+            assert_eq!(format!("{:?}", d), format!("{:?}", decoded));
+            assert_eq!(d.apply_to_string(TEST_STR), decoded.apply_to_string(TEST_STR));
+        }
+    }
+
+    #[test]
+    fn binary_malformed() {
+        assert_eq!(Err(DecodeError::UnexpectedEof), Delta::decode_binary(&[]).map(|_| ()));
+        assert_eq!(Err(DecodeError::UnsupportedVersion(9)), Delta::decode_binary(&[9, 0]).map(|_| ()));
+        assert_eq!(Err(DecodeError::InvalidRange), Delta::decode_binary(&[1, 3, 0, 2, 2]).map(|_| ()));
+        assert_eq!(Err(DecodeError::InvalidUtf8), Delta::decode_binary(&[1, 0, 1, 1, 0xff]).map(|_| ()));
+        let overflow = [1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        assert_eq!(Err(DecodeError::VarintOverflow), Delta::decode_binary(&overflow).map(|_| ()));
+
+        // Truncations and corruptions of a valid encoding must not panic.
+        let d = Delta::simple_edit(Interval::new_closed_open(10, 12), Rope::from("\u{1F4A9}+"), TEST_STR.len());
+        let encoded = d.encode_binary();
+        for i in 0..encoded.len() {
+            let _ = Delta::decode_binary(&encoded[..i]);
+            let mut corrupt = encoded.clone();
+            corrupt[i] ^= 0x5a;
+            let _ = Delta::decode_binary(&corrupt);
+        }
+        let mut seed = 12345u32;
+        for _ in 0..1000 {
+            let bytes = (0..(seed % 24)).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            }).collect::<Vec<_>>();
+            let _ = Delta::decode_binary(&bytes);
+        }
+    }
+
+    #[test]
+    fn map_bookmarks() {
+        let d = Delta::simple_edit(Interval::new_closed_open(3, 7), Rope::from("xy"), 11);
+        assert_eq!("helxyorld", d.apply_to_string("hello world"));
+        let marks = [1, 3, 5, 7, 9];
+        assert_eq!(vec![Some(1), Some(3), None, Some(5), Some(7)], d.map_bookmarks(&marks, false));
+        assert_eq!(vec![Some(1), Some(5), None, Some(5), Some(7)], d.map_bookmarks(&marks, true));
+    }
+
+    #[test]
+    fn partition() {
+        let d = build_delta_1();
+        let expected = d.apply_to_string(TEST_STR);
+        for k in 1..8 {
+            let parts = d.partition(k);
+            assert!(parts.len() <= k);
+            let mut result = String::new();
+            let mut next_start = 0;
+            for (iv, sub) in parts {
+                assert_eq!(next_start, iv.start());
+                next_start = iv.end();
+                result.push_str(&sub.apply_to_string(&TEST_STR[iv.start()..iv.end()]));
+            }
+            assert_eq!(TEST_STR.len(), next_start);
+            assert_eq!(expected, result);
+        }
+        let parts = d.partition(2);
+        assert_eq!(2, parts.len());
+        assert_eq!(Interval::new_closed_open(0, 36), parts[0].0);
+    }
+
+    #[test]
+    fn word_count_delta() {
+        let base = Rope::from("hello world");
+        // Splitting a word.
+        let d = Delta::simple_edit(Interval::new_closed_open(2, 2), Rope::from(" "), 11);
+        assert_eq!(1, d.word_count_delta(&base));
+        // Joining two words.
+        let d = Delta::simple_edit(Interval::new_closed_open(5, 6), Rope::from(""), 11);
+        assert_eq!(-1, d.word_count_delta(&base));
+        // Appending a word, and extending one.
+        let d = Delta::simple_edit(Interval::new_closed_open(11, 11), Rope::from(" again"), 11);
+        assert_eq!(1, d.word_count_delta(&base));
+        let d = Delta::simple_edit(Interval::new_closed_open(11, 11), Rope::from("s"), 11);
+        assert_eq!(0, d.word_count_delta(&base));
+        // Several edits whose widened regions overlap.
+        let mut builder = Builder::new(11);
+        builder.replace(Interval::new_closed_open(1, 1), Rope::from(" a b "));
+        builder.delete(Interval::new_closed_open(4, 7));
+        builder.replace(Interval::new_closed_open(9, 9), Rope::from(" "));
+        let d = builder.build();
+        assert_eq!("h a b ellor ld", d.apply_to_string("hello world"));
+        assert_eq!(3, d.word_count_delta(&base));
+    }
+
+    #[test]
+    fn is_whitespace_only() {
+        let base = Rope::from("hello world");
+        let d = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from("  \t"), 11);
+        assert!(d.is_whitespace_only(&base));
+        let d = Delta::simple_edit(Interval::new_closed_open(5, 6), Rope::from("\n"), 11);
+        assert!(d.is_whitespace_only(&base));
+        let d = Delta::simple_edit(Interval::new_closed_open(6, 11), Rope::from(""), 11);
+        assert!(!d.is_whitespace_only(&base));
+        let d = Delta::simple_edit(Interval::new_closed_open(5, 6), Rope::from("_"), 11);
+        assert!(!d.is_whitespace_only(&base));
+        let d = Delta::simple_edit(Interval::new_closed_open(4, 6), Rope::from(" "), 11);
+        assert!(!d.is_whitespace_only(&base));
+    }
+
+    #[test]
+    fn compose_all() {
+        let deltas = vec![
+            Delta::simple_edit(Interval::new_closed_open(1, 3), Rope::from("!"), TEST_STR.len()),
+            Delta::simple_edit(Interval::new_closed_open(5, 20), Rope::from("abc"), TEST_STR.len() - 1),
+            Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("xyz"), TEST_STR.len() - 13),
+            Delta::simple_edit(Interval::new_closed_open(3, 11), Rope::from(""), TEST_STR.len() - 10),
+        ];
+        let mut expected = Rope::from(TEST_STR);
+        for delta in &deltas {
+            expected = delta.apply(&expected);
+        }
+        let composed = Delta::compose_all(&deltas, TEST_STR.len());
+        assert_eq!(String::from(expected), composed.apply_to_string(TEST_STR));
+        assert_eq!(TEST_STR, Delta::compose_all(&[], TEST_STR.len()).apply_to_string(TEST_STR));
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.delete(Interval::new_closed_open(10, 36));
+        builder.replace(Interval::new_closed_open(39, 42), Rope::from("DEEF"));
+        let d = builder.build();
+        let expected = d.apply_to_string(TEST_STR);
+        assert_eq!(expected, Delta::compose_all(&[d], TEST_STR.len()).apply_to_string(TEST_STR));
+    }
+
+    // Whether two deltas have identical elements, not just the same effect.
+    fn same_els(a: &Delta<RopeInfo>, b: &Delta<RopeInfo>) -> bool {
+        a.base_len == b.base_len && a.els.len() == b.els.len() &&
+            a.els.iter().zip(b.els.iter()).all(|pair| match pair {
+                (&DeltaElement::Copy(b1, e1), &DeltaElement::Copy(b2, e2)) => b1 == b2 && e1 == e2,
+                (&DeltaElement::Insert(ref n1), &DeltaElement::Insert(ref n2)) => String::from(n1) == String::from(n2),
+                _ => false,
+            })
+    }
+
+    #[test]
+    fn coalesce() {
+        let d = Delta {
+            els: vec![DeltaElement::Copy(0, 2), DeltaElement::Copy(2, 4), DeltaElement::Insert(Rope::from("a")),
+                DeltaElement::Insert(Rope::from("")), DeltaElement::Insert(Rope::from("b")),
+                DeltaElement::Copy(5, 5), DeltaElement::Copy(6, 8)],
+            base_len: 10,
+        };
+        let expected = Delta {
+            els: vec![DeltaElement::Copy(0, 4), DeltaElement::Insert(Rope::from("ab")), DeltaElement::Copy(6, 8)],
+            base_len: 10,
+        };
+        assert_eq!(d.apply_to_string("0123456789"), expected.apply_to_string("0123456789"));
+        assert!(same_els(&expected, &d.coalesce()));
+    }
+
+    #[test]
+    fn synthesize_is_canonical() {
+        // A small deterministic generator standing in for a fuzzer.
+        let mut seed: u32 = 12345;
+        let mut rand = move |n: usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as usize % n
+        };
+        let union_str = Rope::from(TEST_STR);
+        for _ in 0..200 {
+            let mut subsets = Vec::new();
+            for _ in 0..2 {
+                let mut sb = SubsetBuilder::new();
+                let mut x = rand(4);
+                while x < TEST_STR.len() {
+                    let end = min(x + 1 + rand(6), TEST_STR.len());
+                    sb.add_range(x, end);
+                    x = end + 1 + rand(6);
+                }
+                subsets.push(sb.build());
+            }
+            let d = Delta::synthesize(&union_str, &subsets[0], &subsets[1]);
+            assert!(same_els(&d, &d.clone().coalesce()));
+            let base = subsets[0].delete_from_string(TEST_STR);
+            assert_eq!(subsets[1].delete_from_string(TEST_STR), d.apply_to_string(&base));
+        }
+    }
+
+    #[test]
+    fn restrict_to_base_range() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(12, 15), Rope::from("xyz!"));
+        builder.delete(Interval::new_closed_open(20, 24));
+        builder.replace(Interval::new_closed_open(26, 26), Rope::from("+"));
+        let d = builder.build();
+        let new = d.apply_to_string(TEST_STR);
+        for &(lo, hi) in &[(10, 30), (12, 27), (0, TEST_STR.len())] {
+            let restricted = d.restrict_to_base_range(lo, hi);
+            assert_eq!(&new[lo..hi + new.len() - TEST_STR.len()], restricted.apply_to_string(&TEST_STR[lo..hi]));
+        }
+        // The insert at 26 belongs to the range after it.
+        assert_eq!("+QRSTUVWXYZ", d.restrict_to_base_range(26, 36).apply_to_string(&TEST_STR[26..36]));
+        assert_eq!("OP", d.restrict_to_base_range(20, 26).apply_to_string(&TEST_STR[20..26]));
+    }
+
+    #[test]
+    fn apply_cow() {
+        let base = Rope::from(TEST_STR);
+        let identity = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from(""), TEST_STR.len());
+        assert!(identity.is_identity());
+        match identity.apply_cow(&base) {
+            Cow::Borrowed(r) => assert_eq!(TEST_STR, String::from(r)),
+            Cow::Owned(_) => panic!("identity delta should borrow"),
+        }
+        let d = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from("!"), TEST_STR.len());
+        assert!(!d.is_identity());
+        match d.apply_cow(&base) {
+            Cow::Borrowed(_) => panic!("edit should produce a new rope"),
+            Cow::Owned(r) => assert_eq!(d.apply_to_string(TEST_STR), String::from(r)),
+        }
+    }
+
+    #[test]
+    fn from_diff() {
+        let cases = [("hello world", "hello, world"), ("abc", "abc"), ("", "new"), ("old", ""),
+            ("aaa", "aaaa"), ("x\u{e9}y", "x\u{e8}y"), ("\u{e9}", "\u{e8}\u{e9}")];
+        for &(base, target) in &cases {
+            let d = Delta::from_diff(&Rope::from(base), &Rope::from(target));
+            assert_eq!(target, d.apply_to_string(base));
+        }
+        let d = Delta::from_diff(&Rope::from("hello world"), &Rope::from("hello, world"));
+        assert_eq!(vec![TextEdit { start: 5, end: 5, new_text: ",".to_owned() }],
+            d.to_text_edits(&Rope::from("hello world")));
+    }
+
+    #[test]
+    fn inserted_slices() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(1, 3), Rope::from("!"));
+        builder.replace(Interval::new_closed_open(10, 10), Rope::from("inserted"));
+        let d = builder.build();
+        let slices: Vec<Cow<str>> = d.inserted_slices().collect();
+        assert_eq!(2, slices.len());
+        assert!(slices.iter().all(|s| match *s { Cow::Borrowed(_) => true, Cow::Owned(_) => false }));
+        assert_eq!("!inserted", d.all_inserted());
+        assert_eq!(d.all_inserted(), slices.concat());
+
+        let big = "a".repeat(5000);
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from(&big), 0);
+        assert_eq!(big, d.inserted_slices().collect::<String>());
+        assert!(Delta::simple_edit(Interval::new_closed_open(0, 1), Rope::from(""), 1).all_inserted().is_empty());
+    }
+
+    #[test]
+    fn from_subset_and_inserts() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(1, 3), Rope::from("!"));
+        builder.delete(Interval::new_closed_open(10, 36));
+        builder.replace(Interval::new_closed_open(42, 45), Rope::from("GI"));
+        builder.replace(Interval::new_closed_open(54, 54), Rope::from("888"));
+        builder.replace(Interval::new_closed_open(59, 60), Rope::from("HI"));
+        builder.replace(Interval::new_closed_open(62, 62), Rope::from("$"));
+        let d = builder.build();
+
+        let mut sb = SubsetBuilder::new();
+        for &(b, e) in &[(1, 3), (10, 36), (42, 45), (59, 60)] {
+            sb.add_range(b, e);
+        }
+        let inserts = vec![(1, Rope::from("!")), (42, Rope::from("G")), (45, Rope::from("I")),
+            (54, Rope::from("888")), (60, Rope::from("HI")), (62, Rope::from("$"))];
+        let d2 = Delta::from_subset_and_inserts(TEST_STR.len(), &sb.build(), inserts);
+        assert_eq!(d.apply_to_string(TEST_STR), d2.apply_to_string(TEST_STR));
+        assert_eq!(d.copied_mapping(), d2.copied_mapping());
+    }
+
+    #[test]
+    fn split_large_inserts() {
+        let family = "\u{1F469}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let text = format!("ae\u{301}{}o\u{308}\u{308}xy", family);
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(1, 3), Rope::from("!"));
+        builder.replace(Interval::new_closed_open(10, 10), Rope::from(text.as_str()));
+        let d = builder.build();
+
+        let pieces = |max_len| {
+            let split = d.split_large_inserts(max_len);
+            assert_eq!(d.apply_to_string(TEST_STR), split.apply_to_string(TEST_STR));
+            split.inserted_slices().skip(1).map(String::from).collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["a", "e\u{301}", family, "o\u{308}\u{308}", "x", "y"], pieces(1));
+        assert_eq!(vec!["ae\u{301}", family, "o\u{308}\u{308}", "xy"], pieces(3));
+        assert_eq!(vec![format!("ae\u{301}{}", family), "o\u{308}\u{308}xy".to_owned()], pieces(8));
+        assert_eq!(vec![text.clone()], pieces(100));
+    }
+
+    #[test]
+    fn apply_deletes_only() {
+        let d = build_delta_2();
+        let base = Rope::from(TEST_STR);
+        let deleted = d.apply_deletes_only(&base);
+        assert_eq!(String::from(d.clone().factor().1.delete_from(&base)), String::from(&deleted));
+        assert_eq!("03456789abcdefjklmnopqrstuvwyz", String::from(deleted));
+    }
+
+    #[test]
+    fn annotate() {
+        let d = build_delta_1();
+        let annotations = d.annotate();
+        let mut x = 0;
+        for &(iv, _) in &annotations {
+            assert_eq!(x, iv.start());
+            assert!(iv.end() > iv.start());
+            x = iv.end();
+        }
+        assert_eq!(d.new_document_len(), x);
+        let new = d.apply_to_string(TEST_STR);
+        for &(iv, annotation) in &annotations {
+            if let Annotation::Unchanged { old_start } = annotation {
+                assert_eq!(&TEST_STR[old_start..old_start + iv.end() - iv.start()], &new[iv.start()..iv.end()]);
+            }
+        }
+        assert_eq!((Interval::new_closed_open(13, 17), Annotation::Inserted), annotations[2]);
+        assert_eq!((Interval::new_closed_open(0, 10), Annotation::Unchanged { old_start: 0 }), annotations[0]);
+    }
+
+    #[test]
+    fn merge_inserts() {
+        let mk = |offset: usize, text: &str| {
+            let d = Delta::simple_edit(Interval::new_closed_open(offset, offset), Rope::from(text), TEST_STR.len());
+            d.factor().0
+        };
+        let deltas = vec![mk(20, "b"), mk(3, "a"), mk(TEST_STR.len(), "c")];
+        let merged = InsertDelta::merge_inserts(TEST_STR.len(), &deltas).unwrap();
+        let mut expected = TEST_STR.to_owned();
+        expected.insert_str(TEST_STR.len(), "c");
+        expected.insert_str(20, "b");
+        expected.insert_str(3, "a");
+        assert_eq!(expected, merged.apply_to_string(TEST_STR));
+        assert_eq!(TEST_STR, InsertDelta::merge_inserts(TEST_STR.len(), &[]).unwrap().apply_to_string(TEST_STR));
+    }
+
+    #[test]
+    fn merge_inserts_same_position() {
+        let mk = |offset: usize, text: &str, len: usize| {
+            let d = Delta::simple_edit(Interval::new_closed_open(offset, offset), Rope::from(text), len);
+            d.factor().0
+        };
+        let deltas = vec![mk(5, "a", TEST_STR.len()), mk(5, "b", TEST_STR.len())];
+        assert_eq!(Some(MergeError::SamePosition(5)), InsertDelta::merge_inserts(TEST_STR.len(), &deltas).err());
+        let deltas = vec![mk(5, "a", TEST_STR.len()), mk(5, "b", 10)];
+        assert_eq!(Some(MergeError::BaseLenMismatch), InsertDelta::merge_inserts(TEST_STR.len(), &deltas).err());
+    }
+
+    #[test]
+    fn copied_mapping() {
+        let d = build_delta_2();
+        let mapping = d.copied_mapping();
+        let ranges: Vec<(usize, usize, usize)> = mapping.iter()
+            .map(|&(iv, x)| (iv.start(), iv.end(), x)).collect();
+        assert_eq!(vec![(0, 1, 0), (3, 10, 2), (36, 42, 9), (45, 54, 17), (54, 59, 29), (60, 62, 36)], ranges);
+        let base = Rope::from(TEST_STR);
+        let new = d.apply(&base);
+        for &(iv, x) in &mapping {
+            assert_eq!(base.slice_to_string(iv.start(), iv.end()),
+                new.slice_to_string(x, x + iv.end() - iv.start()));
+        }
+    }
+
+    #[test]
+    fn to_text_edits() {
+        let d = build_delta_2();
+        let edits = d.to_text_edits(&Rope::from(TEST_STR));
+        assert_eq!(5, edits.len());
+        assert_eq!(TextEdit { start: 54, end: 54, new_text: "888".to_owned() }, edits[3]);
+        let mut text = String::from(TEST_STR);
+        for edit in edits.iter().rev() {
+            text = format!("{}{}{}", &text[..edit.start], edit.new_text, &text[edit.end..]);
+        }
+        assert_eq!(d.apply_to_string(TEST_STR), text);
+        assert_eq!("0!3456789abcdefGIjklmnopqr888stuvwHIyz", text);
+    }
+
     #[test]
     fn factor() {
         let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
@@ -521,6 +2678,16 @@ mod tests {
         assert_eq!("hld", ss.delete_from_string("hello world"));
     }
 
+    #[test]
+    fn factor_full() {
+        let d = build_delta_1();
+        let (ins, dels) = d.clone().factor();
+        let (ins_full, dels_full, inserted_full) = d.factor_full();
+        assert_eq!(ins.apply_to_string(TEST_STR), ins_full.apply_to_string(TEST_STR));
+        assert_eq!(dels, dels_full);
+        assert_eq!(ins.inserted_subset(), inserted_full);
+    }
+
     #[test]
     fn synthesize() {
         let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
@@ -555,6 +2722,18 @@ mod tests {
         assert_eq!("0123456789ABCDEFGHIJKLMNOP+QRSTUVWXYZabcdefghijklmnopqrstuvwxyz", d4.apply_to_string(TEST_STR));
     }
 
+    #[test]
+    fn transform_expand_biased() {
+        let str1 = "01259DGJKNQTUVWXYcdefghkmopqrstvwxy";
+        let s1 = find_deletions(str1, TEST_STR);
+        let d = Delta::simple_edit(Interval::new_closed_open(10, 10), Rope::from("+"), str1.len());
+        let (d2, _ss) = d.factor();
+        let left = d2.transform_expand_biased(&s1, TEST_STR.len(), InsertBias::Left);
+        assert_eq!("0123456789ABCDEFGHIJKLMN+OPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", left.apply_to_string(TEST_STR));
+        let right = d2.transform_expand_biased(&s1, TEST_STR.len(), InsertBias::Right);
+        assert_eq!("0123456789ABCDEFGHIJKLMNOP+QRSTUVWXYZabcdefghijklmnopqrstuvwxyz", right.apply_to_string(TEST_STR));
+    }
+
     #[test]
     fn transform_shrink() {
         let d = Delta::simple_edit(Interval::new_closed_open(10, 12), Rope::from("+"), TEST_STR.len());
@@ -571,4 +2750,398 @@ mod tests {
         let d4 = d2.transform_shrink(&s2);
         assert_eq!("356789+ABCx", d4.apply_to_string(str2));
     }
+
+    #[test]
+    fn unchanged_prefix_suffix_len() {
+        let d = Delta::simple_edit(Interval::new_closed_open(10, 12), Rope::from("x"), TEST_STR.len());
+        assert_eq!(10, d.unchanged_prefix_len());
+        assert_eq!(TEST_STR.len() - 12, d.unchanged_suffix_len());
+        let d = Delta::simple_edit(Interval::new_closed_open(0, TEST_STR.len()), Rope::from("x"), TEST_STR.len());
+        assert_eq!(0, d.unchanged_prefix_len());
+        assert_eq!(0, d.unchanged_suffix_len());
+    }
+
+    #[test]
+    fn is_append_only() {
+        let n = TEST_STR.len();
+        assert!(Delta::simple_edit(Interval::new_closed_open(n, n), Rope::from("!"), n).is_append_only());
+        assert!(!Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("!"), n).is_append_only());
+        assert!(!Delta::simple_edit(Interval::new_closed_open(10, 10), Rope::from("!"), n).is_append_only());
+        assert!(!Delta::simple_edit(Interval::new_closed_open(n - 1, n), Rope::from("!"), n).is_append_only());
+        assert!(!Delta::simple_edit(Interval::new_closed_open(n, n), Rope::from(""), n).is_append_only());
+        assert!(Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("!"), 0).is_append_only());
+    }
+
+    #[test]
+    fn new_doc_hash() {
+        let base = Rope::from(TEST_STR);
+        let base_hash = Delta::doc_hash(&base);
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(3, 5), Rope::from("abc"));
+        let partial_hash = builder.running_hash();
+        assert_ne!(0, partial_hash);
+        builder.delete(Interval::new_closed_open(10, 11));
+        assert_eq!(partial_hash, builder.running_hash());
+        builder.replace(Interval::new_closed_open(40, 40), Rope::from("\u{1F4A9}"));
+        assert_ne!(partial_hash, builder.running_hash());
+        // Rolling the hash forward gives the same result as hashing at once.
+        let mut fresh_builder = Builder::new(TEST_STR.len());
+        fresh_builder.replace(Interval::new_closed_open(3, 5), Rope::from("abc"));
+        fresh_builder.delete(Interval::new_closed_open(10, 11));
+        fresh_builder.replace(Interval::new_closed_open(40, 40), Rope::from("\u{1F4A9}"));
+        assert_eq!(fresh_builder.running_hash(), builder.running_hash());
+        let deltas = vec![
+            builder.build(),
+            Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("x"), TEST_STR.len()),
+            Delta::simple_edit(Interval::new_closed_open(0, TEST_STR.len()), Rope::from("x"), TEST_STR.len()),
+            Delta::simple_edit(Interval::new_closed_open(10, 10), Rope::from(""), TEST_STR.len()),
+        ];
+        for d in deltas {
+            let expected = Delta::doc_hash(&d.apply(&base));
+            assert_eq!(expected, d.new_doc_hash(base_hash, &base));
+        }
+        // A base spanning many leaves.
+        let long_str = TEST_STR.repeat(100);
+        let long_base = Rope::from(&long_str);
+        let mut builder = Builder::new(long_str.len());
+        builder.replace(Interval::new_closed_open(1000, 3000), Rope::from(TEST_STR));
+        builder.delete(Interval::new_closed_open(4000, 4001));
+        let d = builder.build();
+        let expected = Delta::doc_hash(&d.apply(&long_base));
+        assert_eq!(expected, d.new_doc_hash(Delta::doc_hash(&long_base), &long_base));
+        assert_ne!(base_hash, Delta::doc_hash(&Rope::from("0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxzy")));
+    }
+
+    #[test]
+    fn to_micro_ops() {
+        let mut builder = Builder::new(11);
+        builder.replace(Interval::new_closed_open(1, 3), Rope::from("\u{1F4A9}a"));
+        builder.delete(Interval::new_closed_open(6, 7));
+        builder.replace(Interval::new_closed_open(11, 11), Rope::from("!"));
+        let d = builder.build();
+        let base = Rope::from("hello world");
+        let ops = d.to_micro_ops(&base);
+        assert_eq!(&[MicroOp::DeleteChar { at: 1 }, MicroOp::DeleteChar { at: 1 },
+            MicroOp::InsertChar { at: 1, ch: '\u{1F4A9}' }, MicroOp::InsertChar { at: 5, ch: 'a' }],
+            &ops[..4]);
+        let mut s = String::from("hello world");
+        for op in ops {
+            match op {
+                MicroOp::InsertChar { at, ch } => s.insert(at, ch),
+                MicroOp::DeleteChar { at } => { s.remove(at); }
+            }
+        }
+        assert_eq!(d.apply_to_string("hello world"), s);
+        assert_eq!("h\u{1F4A9}alo orld!", s);
+    }
+
+    #[test]
+    fn to_text_edits_merged() {
+        let mut builder = Builder::new(11);
+        builder.replace(Interval::new_closed_open(1, 2), Rope::from("a"));
+        builder.delete(Interval::new_closed_open(3, 4));
+        builder.replace(Interval::new_closed_open(8, 9), Rope::from("!"));
+        let d = builder.build();
+        let base = Rope::from("hello world");
+        assert_eq!(d.to_text_edits(&base), d.to_text_edits_merged(&base, 0));
+        let merged = d.to_text_edits_merged(&base, 1);
+        assert_eq!(vec![
+            TextEdit { start: 1, end: 4, new_text: "al".to_owned() },
+            TextEdit { start: 8, end: 9, new_text: "!".to_owned() },
+        ], merged);
+        let merged = d.to_text_edits_merged(&base, 4);
+        assert_eq!(vec![TextEdit { start: 1, end: 9, new_text: "alo wo!".to_owned() }], merged);
+        let mut s = String::from("hello world");
+        for edit in merged.iter().rev() {
+            s.replace_range(edit.start..edit.end, &edit.new_text);
+        }
+        assert_eq!(d.apply_to_string("hello world"), s);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "synthesized delta has the wrong length")]
+    fn synthesize_checks_lengths() {
+        // Overlapping ranges are counted twice by `len_after_delete`.
+        let new_dels = Subset::from_ranges_for_test(vec![(2, 6), (4, 8)]);
+        Delta::synthesize(&Rope::from("0123456789"), &Subset::default(), &new_dels);
+    }
+
+    #[test]
+    fn commutes_with() {
+        let n = TEST_STR.len();
+        let a = Delta::simple_edit(Interval::new_closed_open(1, 3), Rope::from("xy"), n);
+        let b = Delta::simple_edit(Interval::new_closed_open(10, 10), Rope::from("z"), n);
+        let c = Delta::simple_edit(Interval::new_closed_open(2, 12), Rope::from(""), n);
+        let d = Delta::simple_edit(Interval::new_closed_open(3, 4), Rope::from(""), n);
+        assert!(a.commutes_with(&b));
+        assert!(b.commutes_with(&a));
+        assert!(!a.commutes_with(&c));
+        assert!(!b.commutes_with(&c));
+        assert!(!a.commutes_with(&d));
+        assert!(b.commutes_with(&d));
+        assert!(!b.commutes_with(&b));
+        assert!(!a.commutes_with(&Delta::simple_edit(Interval::new_closed_open(1, 3), Rope::from("xy"), n + 1)));
+    }
+
+    #[test]
+    fn is_prefix_of() {
+        let mut builder = Builder::new(11);
+        builder.delete(Interval::new_closed_open(0, 1));
+        builder.replace(Interval::new_closed_open(5, 5), Rope::from(","));
+        let d = builder.build();
+        let mut builder = Builder::new(11);
+        builder.delete(Interval::new_closed_open(0, 1));
+        builder.replace(Interval::new_closed_open(5, 5), Rope::from(", dear"));
+        builder.replace(Interval::new_closed_open(11, 11), Rope::from("!"));
+        let extended = builder.build();
+        assert!(d.is_prefix_of(&d));
+        assert!(d.is_prefix_of(&extended));
+        assert!(!extended.is_prefix_of(&d));
+        let mut builder = Builder::new(11);
+        builder.replace(Interval::new_closed_open(5, 5), Rope::from(", dear"));
+        let diverged = builder.build();
+        assert!(!d.is_prefix_of(&diverged));
+        let other_text = Delta::simple_edit(Interval::new_closed_open(0, 1), Rope::from("j"), 11);
+        assert!(!Delta::simple_edit(Interval::new_closed_open(0, 1), Rope::from("y"), 11)
+            .is_prefix_of(&other_text));
+        assert!(Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from(""), 11)
+            .is_prefix_of(&other_text));
+    }
+
+    #[test]
+    fn empty_base() {
+        let empty = Rope::from("");
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("abc"), 0);
+        assert_eq!("abc", String::from(d.apply(&empty)));
+        assert_eq!("abc", d.apply_to_string(""));
+        assert_eq!(3, d.new_document_len());
+        assert_eq!((Interval::new_closed_open(0, 0), 3), d.summary());
+        assert_eq!(DeltaClass::PureInsert, d.classify());
+        assert_eq!(0, d.unchanged_prefix_len());
+        assert_eq!(0, d.unchanged_suffix_len());
+        assert_eq!(vec![Some(0)], d.map_bookmarks(&[0], false));
+        assert_eq!(vec![Some(3)], d.map_bookmarks(&[0], true));
+        let (ins, del) = d.clone().factor();
+        assert!(del.is_empty());
+        assert_eq!("abc", String::from(ins.apply(&empty)));
+        // The inverse, recovered from the deletions the insert would undo.
+        let text = d.apply(&empty);
+        let ins_subset = ins.inserted_subset();
+        let inverse = Delta::synthesize(&text, &Subset::default(), &ins_subset);
+        assert_eq!(3, inverse.base_len);
+        assert_eq!("", inverse.apply_to_string("abc"));
+        assert_eq!(vec![TextEdit { start: 0, end: 0, new_text: "abc".to_owned() }], d.to_text_edits(&empty));
+        let partitions = d.partition(2);
+        assert_eq!(1, partitions.len());
+        assert_eq!("abc", partitions[0].1.apply_to_string(""));
+
+        let identity = Builder::<RopeInfo>::new(0).build();
+        assert!(identity.is_identity());
+        assert_eq!(0, identity.new_document_len());
+        assert_eq!((Interval::new_closed_open(0, 0), 0), identity.summary());
+        assert!(identity.clone().factor().1.is_empty());
+        assert_eq!("", String::from(identity.apply(&empty)));
+    }
+
+    #[test]
+    fn is_in_place_overwrite() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(1, 3), Rope::from("ab"));
+        builder.replace(Interval::new_closed_open(10, 11), Rope::from("c"));
+        builder.replace(Interval::new_closed_open(TEST_STR.len() - 1, TEST_STR.len()), Rope::from("d"));
+        let d = builder.build();
+        assert!(d.is_length_preserving());
+        assert!(d.is_in_place_overwrite());
+
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.delete(Interval::new_closed_open(1, 3));
+        builder.replace(Interval::new_closed_open(10, 10), Rope::from("ab"));
+        let d = builder.build();
+        assert!(d.is_length_preserving());
+        assert!(!d.is_in_place_overwrite());
+
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 3), Rope::from("abc"), TEST_STR.len());
+        assert!(!d.is_length_preserving());
+        assert!(!d.is_in_place_overwrite());
+        assert!(Builder::<RopeInfo>::new(10).build().is_in_place_overwrite());
+    }
+
+    #[test]
+    fn checked_transform() {
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        let mut xform = Transformer::new(&d);
+        assert_eq!(Some(0), xform.checked_transform(0, false));
+        assert_eq!(Some(4), xform.checked_transform(9, true));
+        assert_eq!(Some(6), xform.checked_transform(11, true));
+        assert_eq!(None, xform.checked_transform(12, false));
+    }
+
+    #[test]
+    fn expect_full_coverage() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.delete(Interval::new_closed_open(10, 36));
+        builder.replace(Interval::new_closed_open(39, 42), Rope::from("DEEF"));
+        builder.replace(Interval::new_closed_open(50, 50), Rope::from("x"));
+        let d = builder.expect_full_coverage().unwrap();
+        assert_eq!("0123456789abcDEEFghijklmnxopqrstuvwxyz", d.apply_to_string(TEST_STR));
+
+        // Skip past 10..20 without copying or deleting it.
+        let mut builder = Builder::<RopeInfo>::new(TEST_STR.len());
+        builder.delta.els.push(DeltaElement::Copy(0, 10));
+        builder.last_offset = 20;
+        builder.delete(Interval::new_closed_open(30, 40));
+        assert_eq!(Some(BuilderError::Uncovered(10, 20)), builder.expect_full_coverage().err());
+    }
+
+    #[test]
+    fn transform_caret() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.insert(10, Rope::from("abc"), InsertBias::Left);
+        builder.insert(20, Rope::from("def"), InsertBias::Right);
+        let (d, caret_biases) = builder.build_with_caret_biases();
+        let mut transformer = Transformer::with_caret_biases(&d, &caret_biases);
+        assert_eq!(10, transformer.transform_caret(10, None));
+        assert_eq!(26, transformer.transform_caret(20, None));
+        assert_eq!(13, transformer.transform_caret(10, Some(true)));
+        assert_eq!(23, transformer.transform_caret(20, Some(false)));
+        assert_eq!(18, transformer.transform_caret(15, None));
+        assert_eq!(13, Transformer::new(&d).transform_caret(10, None));
+    }
+
+    #[test]
+    fn remap_range() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.delete(Interval::new_closed_open(15, 25));
+        builder.replace(Interval::new_closed_open(30, 30), Rope::from("xy"));
+        let d = builder.build();
+        let remap = |start, end, policy| d.remap_range(Interval::new_closed_open(start, end), policy)
+            .map(|iv| iv.start_end());
+
+        assert_eq!(Some((10, 15)), remap(10, 20, RemapPolicy::Clamp));
+        assert_eq!(None, remap(10, 20, RemapPolicy::DropIfDeleted));
+        assert_eq!(None, remap(10, 20, RemapPolicy::DropIfTouched));
+        assert_eq!(Some((10, 20)), remap(10, 30, RemapPolicy::Clamp));
+        assert_eq!(Some((15, 15)), remap(16, 24, RemapPolicy::Clamp));
+
+        // An insertion inside the range.
+        assert_eq!(Some((18, 27)), remap(28, 35, RemapPolicy::Clamp));
+        assert_eq!(Some((18, 27)), remap(28, 35, RemapPolicy::DropIfDeleted));
+        assert_eq!(None, remap(28, 35, RemapPolicy::DropIfTouched));
+
+        // Untouched ranges, including ones next to the edits.
+        for &policy in &[RemapPolicy::Clamp, RemapPolicy::DropIfDeleted, RemapPolicy::DropIfTouched] {
+            assert_eq!(Some((0, 15)), remap(0, 15, policy));
+            assert_eq!(Some((15, 20)), remap(25, 30, policy));
+            assert_eq!(Some((22, 25)), remap(30, 33, policy));
+        }
+    }
+
+    #[test]
+    fn large_copies() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.delete(Interval::new_closed_open(4, 10));
+        builder.replace(Interval::new_closed_open(40, 40), Rope::from("xyz"));
+        let d = builder.build();
+        let large: Vec<_> = d.large_copies(20).into_iter().map(|(iv, new_start)| (iv.start_end(), new_start)).collect();
+        assert_eq!(vec![((10, 40), 4), ((40, 62), 37)], large);
+        let large: Vec<_> = d.large_copies(25).into_iter().map(|(iv, new_start)| (iv.start_end(), new_start)).collect();
+        assert_eq!(vec![((10, 40), 4)], large);
+
+        let d = Delta {
+            els: vec![DeltaElement::Copy(0, 2), DeltaElement::Copy(2, 4), DeltaElement::Insert(Rope::from("a")),
+                DeltaElement::Copy(6, 8)],
+            base_len: 10,
+        };
+        let large: Vec<_> = d.large_copies(3).into_iter().map(|(iv, new_start)| (iv.start_end(), new_start)).collect();
+        assert_eq!(vec![((0, 4), 0)], large);
+    }
+
+    #[test]
+    fn apply_with_transformer() {
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        let (text, mut transformer) = d.apply_with_transformer(&Rope::from("hello world"));
+        assert_eq!("herald", String::from(text));
+        assert_eq!(4, transformer.transform(9, false));
+        assert_eq!(5, transformer.transform(10, false));
+    }
+
+    #[test]
+    fn summary_degenerate() {
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("abc"), 0);
+        assert_eq!(Some((Interval::new_closed_open(0, 0), 3)), d.summary_checked());
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 11), Rope::from(""), 11);
+        assert_eq!(Some((Interval::new_closed_open(0, 11), 0)), d.summary_checked());
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 11), Rope::from("xy"), 11);
+        assert_eq!((Interval::new_closed_open(0, 11), 2), d.summary());
+
+        let overlapping = Delta::<RopeInfo> {
+            els: vec![DeltaElement::Copy(0, 6), DeltaElement::Copy(4, 10)],
+            base_len: 10,
+        };
+        assert_eq!(None, overlapping.summary_checked());
+        let past_end = Delta::<RopeInfo> {
+            els: vec![DeltaElement::Copy(0, 12)],
+            base_len: 10,
+        };
+        assert_eq!(None, past_end.summary_checked());
+    }
+
+    #[test]
+    fn element_costs() {
+        let d = build_delta_1();
+        let costs = d.element_costs();
+        assert_eq!(vec![10, 3, 4, 12, 3, 4, 1], costs);
+        assert_eq!(d.new_document_len(), costs.iter().sum::<usize>());
+    }
+
+    #[test]
+    fn to_subsets() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(0, 0), Rope::from("^"));
+        builder.delete(Interval::new_closed_open(10, 36));
+        builder.replace(Interval::new_closed_open(39, 42), Rope::from("DEEF"));
+        builder.replace(Interval::new_closed_open(54, 54), Rope::from("999"));
+        builder.replace(Interval::new_closed_open(54, 54), Rope::from("88"));
+        builder.replace(Interval::new_closed_open(58, 62), Rope::from("$"));
+        let d = builder.build();
+        let (dels, ins, ropes) = d.to_subsets();
+        assert_eq!("^0123456789abcDEEFghijklmnopqr99988stuv$", d.apply_to_string(TEST_STR));
+        assert_eq!("0123456789abcghijklmnopqrstuv", dels.delete_from_string(TEST_STR));
+        let mut sb = SubsetBuilder::new();
+        sb.add_range(0, 1);
+        sb.add_range(14, 18);
+        sb.add_range(30, 35);
+        sb.add_range(39, 40);
+        assert_eq!(sb.build(), ins);
+        assert_eq!(vec!["^", "DEEF", "999", "88", "$"],
+            ropes.iter().map(String::from).collect::<Vec<_>>());
+
+        let rebuilt = Delta::from_subsets(TEST_STR.len(), &dels, &ins, ropes);
+        assert_eq!(d.apply_to_string(TEST_STR), rebuilt.apply_to_string(TEST_STR));
+        let (dels2, ins2, ropes2) = rebuilt.to_subsets();
+        assert_eq!(dels, dels2);
+        assert_eq!(ins, ins2);
+        // Adjacent inserts are merged when rebuilding.
+        assert_eq!(vec!["^", "DEEF", "99988", "$"],
+            ropes2.iter().map(String::from).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn apply_remapping_selections() {
+        let mut builder = Builder::new(TEST_STR.len());
+        builder.replace(Interval::new_closed_open(20, 30), Rope::from("xy"));
+        let d = builder.build();
+        let selections = [
+            Interval::new_closed_open(2, 5),
+            Interval::new_closed_open(15, 25),
+            Interval::new_closed_open(22, 28),
+            Interval::new_closed_open(18, 40),
+            Interval::new_closed_open(35, 35),
+        ];
+        let (text, regions) = d.apply_remapping_selections(&Rope::from(TEST_STR), &selections);
+        assert_eq!(d.apply_to_string(TEST_STR), String::from(text));
+        let regions: Vec<_> = regions.iter().map(|iv| iv.start_end()).collect();
+        assert_eq!(vec![(2, 5), (15, 22), (22, 22), (18, 32), (27, 27)], regions);
+    }
 }
@@ -18,17 +18,99 @@
 //! because all operations are serialized in this central engine.
 
 use std::borrow::Cow;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error;
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
 use std;
+use std::cell::RefCell;
+#[cfg(test)]
+use std::cell::Cell;
 
 use rope::{Rope, RopeInfo};
+use tree::{Cursor, Node, NodeInfo};
 use subset::Subset;
-use delta::Delta;
+use interval::Interval;
+use delta::{Delta, InsertBias};
+
+// Counts the folds run by `deletes_from_union_for_index` on a cache miss, so
+// tests can check repeated queries hit the cache.
+#[cfg(test)]
+thread_local!(static UNION_FOLDS: Cell<usize> = Cell::new(0));
+
+// When set, the next `mk_new_rev` replaces its delta with a malformed one that
+// inserts past the end of the base, so tests can check that the resulting
+// panic leaves the engine unchanged.
+#[cfg(test)]
+thread_local!(static MALFORM_NEXT_EDIT: Cell<bool> = Cell::new(false));
 
-pub struct Engine {
+/// A CRDT-based history of edits to a document.
+///
+/// Methods that look up a revision by id return an `Option` or a `Result`
+/// when it can't be found (for example, because it has been gc'ed), except
+/// for `delta_rev_head` and the `edit_rev` family, which panic; each of these
+/// has a `try_` counterpart that returns an `EngineError` instead, so that a
+/// server can avoid panicking on a stale base revision.
+///
+/// The engine works over any tree of `NodeInfo` whose leaves can be viewed as
+/// bytes (for checksums); `RopeEngine` is the usual instantiation over
+/// `Rope`.
+pub struct Engine<N: NodeInfo> {
     rev_id_counter: usize,
-    union_str: Rope,
+    union_str: Node<N>,
     revs: Vec<Revision>,
+    delete_policy: DeletePolicy,
+    /// Regions deleted by a revision that a concurrent edit also deleted,
+    /// by revision id, in union string coordinates; see `delete_conflicts`.
+    delete_conflicts: Vec<(usize, Subset)>,
+    /// Plugins that may still refer to revisions, which blocks `flush_gc`.
+    plugins: BTreeSet<usize>,
+    next_plugin_id: usize,
+    /// Undo groups whose gc was requested while plugins were registered.
+    pending_gc_groups: BTreeSet<usize>,
+    /// The text of the head revision, kept up to date so it can be read
+    /// without being recomputed.
+    text: Node<N>,
+    /// An undo group greater than any used so far, for `begin_transaction`.
+    next_undo_group: usize,
+    /// Whether history is kept for undo; see `new_no_history`.
+    keep_history: bool,
+    /// Decides whether each edit is accepted; see `set_edit_filter`.
+    edit_filter: Option<Box<dyn Fn(&Delta<N>) -> bool + Send>>,
+    /// The longest the head may be made by an edit; see `set_max_len`.
+    max_len: Option<usize>,
+    /// `deletes_from_union_for_index` results that needed a fold, by
+    /// revision index. Cleared whenever `revs` or `union_str` change.
+    union_deletes_cache: RefCell<BTreeMap<usize, Arc<Subset>>>,
+    /// The first revision, made by `new` or `reset_contents`, and its
+    /// content and checksum, for `is_pristine`.
+    genesis_rev_id: usize,
+    genesis_text: Node<N>,
+    genesis_checksum: u64,
+    /// Incremented by every change to `revs` or `union_str`; see `version`.
+    version: u64,
+}
+
+// The deletes from the union string of a revision's content, as returned by
+// `deletes_from_union_for_index`: borrowed from the revision when no later
+// inserts need to be folded in, or otherwise shared with the cache.
+#[derive(PartialEq)]
+enum UnionDeletes<'a> {
+    Stored(&'a Subset),
+    Cached(Arc<Subset>),
+}
+
+impl<'a> Deref for UnionDeletes<'a> {
+    type Target = Subset;
+
+    fn deref(&self) -> &Subset {
+        match *self {
+            UnionDeletes::Stored(subset) => subset,
+            UnionDeletes::Cached(ref subset) => subset,
+        }
+    }
 }
 
 struct Revision {
@@ -36,14 +118,126 @@ struct Revision {
     deletes_from_union: Subset,
     union_str_len: usize,
     edit: Contents,
+    /// Optional client-defined annotation, such as the name of the command
+    /// that produced the revision.
+    meta: Option<String>,
+    /// The session that authored the revision, if known.
+    author: Option<SessionId>,
+    /// A checksum of the revision's content, for detecting corruption with
+    /// `verify_rev`. It is computed when the revision is created, from the
+    /// previous head's checksum with `Delta::new_doc_hash`, so the whole
+    /// document isn't rehashed for each edit.
+    checksum: u64,
+}
+
+/// The checksum of a document's content, as recorded for each revision and
+/// compared by `verify_rev` and `catch_up`. This is `Delta::doc_hash`, whose
+/// algorithm is fixed, so clients can compute it themselves.
+pub fn content_checksum<N: NodeInfo>(text: &Node<N>) -> u64 where N::L: AsRef<[u8]> {
+    Delta::doc_hash(text)
+}
+
+// Whether `a` and `b` hold the same bytes, however their leaves are split.
+fn same_content<N: NodeInfo>(a: &Node<N>, b: &Node<N>) -> bool where N::L: AsRef<[u8]> {
+    fn leaves<N: NodeInfo>(n: &Node<N>) -> Vec<&[u8]> where N::L: AsRef<[u8]> {
+        let mut leaves = Vec::new();
+        let mut cursor = Cursor::new(n, 0);
+        while let Some((leaf, _)) = cursor.get_leaf() {
+            leaves.push(leaf.as_ref());
+            if cursor.next_leaf().is_none() {
+                break;
+            }
+        }
+        leaves
+    }
+    a.len() == b.len()
+        && leaves(a).iter().flat_map(|leaf| leaf.iter()).eq(leaves(b).iter().flat_map(|leaf| leaf.iter()))
+}
+
+/// A registration of a plugin with an `Engine`, returned by
+/// `Engine::register_plugin`. While any plugin is registered, `flush_gc`
+/// defers reclaiming history.
+#[derive(Debug)]
+pub struct PluginHandle(usize);
+
+/// The outcome of `Engine::flush_gc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GcStatus {
+    /// The requested groups, along with any previously deferred ones, were
+    /// collected.
+    Collected,
+    /// Plugins are still registered, so the groups were set aside to be
+    /// collected by a later `flush_gc`.
+    Deferred,
+}
+
+/// An operation recorded in an engine's history, as returned by
+/// `Engine::operation_log`, with what a peer needs to reproduce it with
+/// `Engine::replay`.
+pub enum EngineOp<N: NodeInfo> {
+    /// An edit, with the delta it applied to the text of `base_rev`.
+    Edit {
+        rev_id: usize,
+        priority: usize,
+        undo_group: usize,
+        base_rev: usize,
+        delta: Delta<N>,
+    },
+    /// An undo, with the complete set of undo groups undone afterwards.
+    Undo {
+        rev_id: usize,
+        groups: BTreeSet<usize>,
+    },
+}
+
+/// The result of `Engine::edit_rev_incremental`.
+pub struct IncrementalUpdate<N: NodeInfo> {
+    /// The id of the head revision after the edit.
+    pub new_rev_id: usize,
+    /// The delta from the previous head to the new one.
+    pub delta: Delta<N>,
+    /// The length of the new head.
+    pub new_head_len: usize,
 }
 
+/// How to bring a client up to date with the head, as computed by
+/// `Engine::catch_up`.
+pub enum CatchUp<N: NodeInfo> {
+    /// A delta that, applied to the client's text, results in the head.
+    Delta(Delta<N>),
+    /// The client's revision is unknown or its text doesn't match, so it
+    /// must replace its text with the head.
+    FullResync(Node<N>),
+}
+
+/// Identifies an editing session (for example, a user or a plugin) that
+/// authors revisions.
+pub type SessionId = usize;
+
 use self::Contents::*;
 
+// A revision to be recomputed by `Engine::rebuild_from`, with what it
+// originally did: for an edit, its priority, undo group, base revision and
+// the delta it applied to the base revision's text.
+struct ReplayEntry<N: NodeInfo> {
+    rev_id: usize,
+    meta: Option<String>,
+    author: Option<SessionId>,
+    step: ReplayStep<N>,
+}
+
+enum ReplayStep<N: NodeInfo> {
+    Edit(usize, usize, usize, Delta<N>),
+    Undo(BTreeSet<usize>),
+}
+
 enum Contents {
     Edit {
         priority: usize,
         undo_group: usize,
+        /// The revision the edit was originally made against, kept so that
+        /// the edit can be replayed.
+        base_rev: usize,
         inserts: Subset,
         deletes: Subset,
     },
@@ -52,21 +246,147 @@ enum Contents {
     }
 }
 
-impl Engine {
-    pub fn new(initial_contents: Rope) -> Engine {
+/// How the engine treats concurrent edits that delete the same text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeletePolicy {
+    /// The deletions are unioned. This is the default.
+    Union,
+    /// The deletions are unioned, and the overlapping regions are also
+    /// recorded, to be queried with `Engine::delete_conflicts`.
+    Detect,
+}
+
+/// An error that occurred while manipulating the engine's history.
+#[derive(Debug, PartialEq)]
+pub enum EngineError {
+    /// The revision with the given id could not be found, possibly because
+    /// it has been gc'ed.
+    MissingRevision(usize),
+    /// The revision with the given id is not an edit.
+    NotAnEdit(usize),
+    /// An edit's delta contains an inserted rope that is not internally
+    /// consistent.
+    MalformedInsert,
+    /// An edit was refused by the engine's edit filter.
+    Rejected,
+    /// An edit would make the head longer than the engine's maximum length.
+    TooLong,
+    /// The revision with the given id prevents `squash`.
+    CannotSquash(usize),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::EngineError::*;
+
+        match *self {
+            MissingRevision(rev_id) => write!(f, "Error: revision {} not found", rev_id),
+            NotAnEdit(rev_id) => write!(f, "Error: revision {} is not an edit", rev_id),
+            MalformedInsert => write!(f, "Error: edit contains a malformed insert"),
+            Rejected => write!(f, "Error: edit rejected by the edit filter"),
+            TooLong => write!(f, "Error: edit exceeds the maximum document length"),
+            CannotSquash(rev_id) => write!(f, "Error: revision {} prevents squashing", rev_id),
+        }
+    }
+}
+
+impl error::Error for EngineError {
+    fn description(&self) -> &str {
+        use self::EngineError::*;
+
+        match *self {
+            MissingRevision(_) => "Revision not found",
+            NotAnEdit(_) => "Revision is not an edit",
+            MalformedInsert => "Edit contains a malformed insert",
+            Rejected => "Edit rejected by the edit filter",
+            TooLong => "Edit exceeds the maximum document length",
+            CannotSquash(_) => "Revision prevents squashing",
+        }
+    }
+}
+
+// The result of one of the panicking `edit_rev` methods: an edit refused by
+// the edit filter or the maximum length changes nothing, but any other error
+// is a bug in the caller.
+fn changed_or_panic(result: Result<bool, EngineError>) -> bool {
+    match result {
+        Ok(changed) => changed,
+        Err(EngineError::Rejected) | Err(EngineError::TooLong) => false,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+impl<N: NodeInfo> Engine<N> where N::L: AsRef<[u8]> {
+    pub fn new(initial_contents: Node<N>) -> Engine<N> {
         let rev = Revision {
             rev_id: 0,
             deletes_from_union: Subset::default(),
             union_str_len: initial_contents.len(),
             edit: Undo { groups: BTreeSet::default() },
+            meta: None,
+            author: None,
+            checksum: content_checksum(&initial_contents),
         };
+        let genesis_checksum = rev.checksum;
         Engine {
             rev_id_counter: 1,
-            union_str: initial_contents,
+            union_str: initial_contents.clone(),
             revs: vec![rev],
+            delete_policy: DeletePolicy::Union,
+            delete_conflicts: Vec::new(),
+            plugins: BTreeSet::new(),
+            next_plugin_id: 0,
+            pending_gc_groups: BTreeSet::new(),
+            text: initial_contents.clone(),
+            next_undo_group: 0,
+            keep_history: true,
+            edit_filter: None,
+            max_len: None,
+            union_deletes_cache: RefCell::new(BTreeMap::new()),
+            genesis_rev_id: 0,
+            genesis_text: initial_contents,
+            genesis_checksum: genesis_checksum,
+            version: 0,
         }
     }
 
+    /// Create an engine that keeps no history: after each edit, every undo
+    /// group is gc'ed, so only the head revision remains and memory use
+    /// doesn't grow with the number of edits. In this mode `undo` and the
+    /// methods built on it do nothing.
+    pub fn new_no_history(initial_contents: Node<N>) -> Engine<N> {
+        let mut engine = Engine::new(initial_contents);
+        engine.keep_history = false;
+        engine
+    }
+
+    /// Replace the whole document with `contents`, starting a fresh history
+    /// as `new` does, for example when reloading a file from disk. Unlike
+    /// `set_head_to`, this computes no diff, but it discards all undo
+    /// history and tombstones. Revision ids keep increasing, so ids from
+    /// before the reset are never reused.
+    pub fn reset_contents(&mut self, contents: Node<N>) {
+        self.genesis_rev_id = self.rev_id_counter;
+        self.genesis_text = contents.clone();
+        self.genesis_checksum = content_checksum(&contents);
+        self.revs = vec![Revision {
+            rev_id: self.rev_id_counter,
+            deletes_from_union: Subset::default(),
+            union_str_len: contents.len(),
+            edit: Undo { groups: BTreeSet::default() },
+            meta: None,
+            author: None,
+            checksum: self.genesis_checksum,
+        }];
+        self.rev_id_counter += 1;
+        self.version += 1;
+        self.union_str = contents.clone();
+        self.text = contents;
+        self.delete_conflicts.clear();
+        self.pending_gc_groups.clear();
+        self.invalidate_union_cache();
+    }
+
     fn get_current_undo(&self) -> Option<&BTreeSet<usize>> {
         for rev in self.revs.iter().rev() {
             if let Undo { ref groups } = rev.edit {
@@ -86,12 +406,17 @@ impl Engine {
     }
 
     /// Get the contents of the document at a given revision number
-    fn rev_content_for_index(&self, rev_index: usize) -> Rope {
+    fn rev_content_for_index(&self, rev_index: usize) -> Node<N> {
         self.deletes_from_union_for_index(rev_index).delete_from(&self.union_str)
     }
 
     /// Get the Subset to delete from the current union string in order to obtain a revision's content
-    fn deletes_from_union_for_index(&self, rev_index: usize) -> Cow<Subset> {
+    fn deletes_from_union_for_index(&self, rev_index: usize) -> UnionDeletes {
+        if let Some(deletes_from_union) = self.union_deletes_cache.borrow().get(&rev_index) {
+            return UnionDeletes::Cached(deletes_from_union.clone());
+        }
+        #[cfg(test)]
+        UNION_FOLDS.with(|folds| folds.set(folds.get() + 1));
         let mut deletes_from_union = Cow::Borrowed(&self.revs[rev_index].deletes_from_union);
         for rev in &self.revs[rev_index + 1..] {
             if let Edit { ref inserts, .. } = rev.edit {
@@ -100,28 +425,206 @@ impl Engine {
                 }
             }
         }
-        deletes_from_union
+        match deletes_from_union {
+            Cow::Borrowed(deletes_from_union) => UnionDeletes::Stored(deletes_from_union),
+            Cow::Owned(deletes_from_union) => {
+                let deletes_from_union = Arc::new(deletes_from_union);
+                self.union_deletes_cache.borrow_mut().insert(rev_index, deletes_from_union.clone());
+                UnionDeletes::Cached(deletes_from_union)
+            }
+        }
+    }
+
+    // Must be called after any change to `revs` or `union_str`, since the
+    // cached subsets depend on both.
+    fn invalidate_union_cache(&mut self) {
+        self.union_deletes_cache.get_mut().clear();
     }
 
     /// Get revision id of head revision.
+    ///
+    /// Every operation that creates a revision, including `undo`, gives it a
+    /// new id greater than any before it, and no operation (including `gc`
+    /// and `reprioritize`) makes an earlier revision the head again, so the
+    /// head revision id never decreases. It doesn't change when `reprioritize`
+    /// or `squash` rewrite the head in place, so use `version` as an
+    /// optimistic-concurrency token.
     pub fn get_head_rev_id(&self) -> usize {
         self.revs.last().unwrap().rev_id
     }
 
+    /// A version number for the engine's state, which never decreases
+    /// across any operation and increases whenever the history or the head
+    /// changes. Unlike the head revision id, it also changes when
+    /// `reprioritize` or `squash` rewrite history while keeping revision ids.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     /// Get text of head revision.
-    pub fn get_head(&self) -> Rope {
-        self.rev_content_for_index(self.revs.len() - 1)
+    pub fn get_head(&self) -> Node<N> {
+        self.text.clone()
+    }
+
+    /// Get a reference to the text of the head revision, without cloning.
+    ///
+    /// The borrow checker ensures the reference can't outlive the next
+    /// mutating call (`edit_rev`, `undo`, `gc` and so on), so it always
+    /// reflects the current head.
+    pub fn head_ref(&self) -> &Node<N> {
+        &self.text
+    }
+
+    /// Write the text of the head revision to `w`, leaf by leaf, without
+    /// first collecting it into a single buffer.
+    pub fn head_write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut cursor = Cursor::new(&self.text, 0);
+        while let Some((leaf, _)) = cursor.get_leaf() {
+            w.write_all(leaf.as_ref())?;
+            if cursor.next_leaf().is_none() {
+                break;
+            }
+        }
+        Ok(())
     }
 
     /// Get text of a given revision, if it can be found.
-    pub fn get_rev(&self, rev: usize) -> Option<Rope> {
+    pub fn get_rev(&self, rev: usize) -> Option<Node<N>> {
         self.find_rev(rev).map(|rev_index| self.rev_content_for_index(rev_index))
     }
 
+    /// Get the text inserted by an edit revision, in order, for example to
+    /// debug where inserts are placed. Returns `None` if the revision can't
+    /// be found or is an undo.
+    pub fn rev_inserted_text(&self, rev_id: usize) -> Option<Node<N>> {
+        let rev_index = self.find_rev(rev_id)?;
+        let inserts = match self.revs[rev_index].edit {
+            Edit { ref inserts, .. } => inserts,
+            Undo { .. } => return None,
+        };
+        let mut ins_from_union = Cow::Borrowed(inserts);
+        for rev in &self.revs[rev_index + 1..] {
+            if let Edit { inserts: ref later_inserts, .. } = rev.edit {
+                if !later_inserts.is_empty() {
+                    ins_from_union = Cow::Owned(ins_from_union.transform_expand(later_inserts));
+                }
+            }
+        }
+        Some(ins_from_union.complement(self.union_str.len()).delete_from(&self.union_str))
+    }
+
+    /// Check a revision's content, reconstructed from history, against the
+    /// checksum recorded when the revision was created. Returns `None` if
+    /// the revision can't be found, and `Some(false)` on a mismatch, which
+    /// indicates a bug in rebasing. Note that `gc` drops text that older
+    /// revisions may have contained, so after it only the head (and
+    /// revisions made since) can be expected to match.
+    pub fn verify_rev(&self, rev_id: usize) -> Option<bool> {
+        self.find_rev(rev_id).map(|rev_index| {
+            self.revs[rev_index].checksum == content_checksum(&self.rev_content_for_index(rev_index))
+        })
+    }
+
+    /// Work out how to catch up a reconnecting client whose last known
+    /// revision is `client_rev`, with content whose `content_checksum` is
+    /// `client_hash`. That is `Delta::doc_hash`, which has a fixed algorithm,
+    /// so clients can compute it, or keep it up to date with
+    /// `Delta::new_doc_hash`. If the revision is still in history and its
+    /// checksum matches, the result is the delta from it to the head;
+    /// otherwise it is the full head text.
+    pub fn catch_up(&self, client_rev: usize, client_hash: u64) -> CatchUp<N> {
+        match self.find_rev(client_rev) {
+            Some(ix) if self.revs[ix].checksum == client_hash => {
+                CatchUp::Delta(self.try_delta_rev_head(client_rev).unwrap())
+            }
+            _ => CatchUp::FullResync(self.get_head()),
+        }
+    }
+
+    /// Get the current length of the union string, which includes the text
+    /// of the head revision and all tombstones still kept for undo.
+    pub fn union_len(&self) -> usize {
+        self.union_str.len()
+    }
+
+    /// Get the total length of the tombstones, the deleted text kept in the
+    /// union string for undo.
+    pub fn tombstone_len(&self) -> usize {
+        self.union_str.len() - self.text.len()
+    }
+
+    /// The length of the tombstones relative to the head text, for
+    /// monitoring whether gc is keeping up. An empty head counts as length 1.
+    pub fn tombstone_ratio(&self) -> f64 {
+        self.tombstone_len() as f64 / std::cmp::max(1, self.text.len()) as f64
+    }
+
+    /// Check the engine's internal invariants, for diagnosing bugs in the
+    /// field: that each revision's subsets fit its union string length, that
+    /// the head text and tombstones add up to the union string, and that
+    /// revision ids are unique and increasing. Returns a description of each
+    /// violation found.
+    pub fn check_invariants(&self) -> Result<(), Vec<String>> {
+        fn check_subset(errors: &mut Vec<String>, rev_id: usize, name: &str, subset: &Subset, len: usize) {
+            let ranges = subset._ranges();
+            for (i, &(beg, end)) in ranges.iter().enumerate() {
+                if beg >= end || (i > 0 && ranges[i - 1].1 >= beg) {
+                    errors.push(format!("revision {}: {} has malformed range {}..{}", rev_id, name, beg, end));
+                }
+            }
+            if let Some(&(_, end)) = ranges.last() {
+                if end > len {
+                    errors.push(format!("revision {}: {} extends to {}, past union_str_len {}",
+                        rev_id, name, end, len));
+                }
+            }
+        }
+
+        let mut errors = Vec::new();
+        for (i, rev) in self.revs.iter().enumerate() {
+            check_subset(&mut errors, rev.rev_id, "deletes_from_union", &rev.deletes_from_union, rev.union_str_len);
+            if let Edit { ref inserts, ref deletes, .. } = rev.edit {
+                check_subset(&mut errors, rev.rev_id, "inserts", inserts, rev.union_str_len);
+                check_subset(&mut errors, rev.rev_id, "deletes", deletes, rev.union_str_len);
+            }
+            if i > 0 && self.revs[i - 1].rev_id >= rev.rev_id {
+                errors.push(format!("revision {} follows revision {}", rev.rev_id, self.revs[i - 1].rev_id));
+            }
+            if rev.rev_id >= self.rev_id_counter {
+                errors.push(format!("revision {} is not below rev_id_counter {}", rev.rev_id, self.rev_id_counter));
+            }
+        }
+        let head = self.revs.last().unwrap();
+        if head.union_str_len != self.union_str.len() {
+            errors.push(format!("head union_str_len {} doesn't match the union string length {}",
+                head.union_str_len, self.union_str.len()));
+        }
+        let tombstones: usize = head.deletes_from_union._ranges().iter().map(|&(beg, end)| end - beg).sum();
+        if self.text.len() + tombstones != head.union_str_len {
+            errors.push(format!("head text length {} plus tombstones length {} isn't union_str_len {}",
+                self.text.len(), tombstones, head.union_str_len));
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Get the length of the union string as of a given revision, if it can
+    /// be found. This is mostly useful for diagnosing divergence between
+    /// replicas.
+    pub fn rev_union_len(&self, rev: usize) -> Option<usize> {
+        self.find_rev(rev).map(|rev_index| self.revs[rev_index].union_str_len)
+    }
+
     /// A delta that, when applied to `base_rev`, results in the current head. Panics
-    /// if there is not at least one edit.
-    pub fn delta_rev_head(&self, base_rev: usize) -> Delta<RopeInfo> {
-        let ix = self.find_rev(base_rev).expect("base revision not found");
+    /// if there is not at least one edit, or if `base_rev` can't be found; see
+    /// `try_delta_rev_head` for a version that returns an error instead.
+    pub fn delta_rev_head(&self, base_rev: usize) -> Delta<N> {
+        self.try_delta_rev_head(base_rev).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `delta_rev_head`, but returns an error if `base_rev` can't be
+    /// found, for example because it has been gc'ed.
+    pub fn try_delta_rev_head(&self, base_rev: usize) -> Result<Delta<N>, EngineError> {
+        let ix = self.find_rev(base_rev).ok_or(EngineError::MissingRevision(base_rev))?;
         let rev = &self.revs[ix];
 
         // Delta::synthesize will add inserts for everything that is in
@@ -139,37 +642,219 @@ impl Engine {
         }
 
         let head_rev = &self.revs.last().unwrap();
-        Delta::synthesize(&self.union_str, &prev_from_union, &head_rev.deletes_from_union)
+        Ok(Delta::synthesize(&self.union_str, &prev_from_union, &head_rev.deletes_from_union))
+    }
+
+    /// A list of `(rev_id, delta)` pairs for every revision after `base_rev`,
+    /// in order, where each delta applies to the content of the revision
+    /// before it. Applying them all in sequence to the contents of `base_rev`
+    /// yields the current head. Returns `None` if `base_rev` can't be found
+    /// (for example, if it has been gc'ed).
+    pub fn deltas_since(&self, base_rev: usize) -> Option<Vec<(usize, Delta<N>)>> {
+        let ix = match self.find_rev(base_rev) {
+            Some(ix) => ix,
+            None => return None,
+        };
+        let mut result = Vec::new();
+        let mut prev_from_union = self.deletes_from_union_for_index(ix);
+        for i in ix + 1..self.revs.len() {
+            let from_union = self.deletes_from_union_for_index(i);
+            let delta = Delta::synthesize(&self.union_str, &prev_from_union, &from_union);
+            result.push((self.revs[i].rev_id, delta));
+            prev_from_union = from_union;
+        }
+        Some(result)
+    }
+
+    /// The edits and undos made after `since_rev`, in order, for replicating
+    /// them to a peer with `replay`. Returns an error if `since_rev`, or the
+    /// base revision of one of the edits, can't be found.
+    pub fn operation_log(&self, since_rev: usize) -> Result<Vec<EngineOp<N>>, EngineError> {
+        let ix = self.find_rev(since_rev).ok_or(EngineError::MissingRevision(since_rev))?;
+        let mut result = Vec::new();
+        for i in ix + 1..self.revs.len() {
+            let rev = &self.revs[i];
+            result.push(match rev.edit {
+                Edit { priority, undo_group, base_rev, .. } => EngineOp::Edit {
+                    rev_id: rev.rev_id,
+                    priority: priority,
+                    undo_group: undo_group,
+                    base_rev: base_rev,
+                    delta: self.edit_delta_for_index(i)?,
+                },
+                Undo { ref groups } => EngineOp::Undo {
+                    rev_id: rev.rev_id,
+                    groups: groups.clone(),
+                },
+            });
+        }
+        Ok(result)
+    }
+
+    /// Apply operations from another engine's `operation_log`, in order. No
+    /// edits may have been made to this engine since the revision the log
+    /// starts from, and base revisions from before the log must name the
+    /// same text in both engines. The ids of revisions created by the log
+    /// need not match: a base revision created by an earlier operation in
+    /// `ops` is mapped to the revision that operation created here. Stops at
+    /// the first error.
+    pub fn replay(&mut self, ops: Vec<EngineOp<N>>) -> Result<(), EngineError> {
+        let mut rev_ids = BTreeMap::new();
+        for op in ops {
+            let rev_id = match op {
+                EngineOp::Edit { rev_id, priority, undo_group, base_rev, delta } => {
+                    let base_rev = rev_ids.get(&base_rev).cloned().unwrap_or(base_rev);
+                    self.try_edit_rev(priority, undo_group, base_rev, delta)?;
+                    rev_id
+                }
+                EngineOp::Undo { rev_id, groups } => {
+                    self.undo(groups);
+                    rev_id
+                }
+            };
+            rev_ids.insert(rev_id, self.get_head_rev_id());
+        }
+        Ok(())
+    }
+
+    /// A delta that, when applied to the current head, restores the content
+    /// of the revision before the most recent edit. Returns `None` if there
+    /// is no edit, or the revision before it is no longer available.
+    pub fn last_edit_inverse(&self) -> Option<Delta<N>> {
+        let last_edit = self.revs.iter().rposition(|rev| match rev.edit {
+            Edit { .. } => true,
+            Undo { .. } => false,
+        });
+        let ix = match last_edit {
+            Some(ix) if ix > 0 => ix,
+            _ => return None,
+        };
+        let prev_from_union = self.deletes_from_union_for_index(ix - 1);
+        let head_rev = &self.revs.last().unwrap();
+        Some(Delta::synthesize(&self.union_str, &head_rev.deletes_from_union, &prev_from_union))
+    }
+
+    /// Set how concurrent deletions of the same text are treated. This only
+    /// affects edits applied after the call.
+    pub fn set_delete_policy(&mut self, policy: DeletePolicy) {
+        self.delete_policy = policy;
+    }
+
+    /// The regions deleted by an edit that were also deleted by a concurrent
+    /// edit, recorded while the policy is `DeletePolicy::Detect`, as
+    /// intervals in the head, in the order the edits were made. Text that is
+    /// still deleted collapses to its position in the head; undoing the
+    /// deletions brings it back into the interval. The regions are kept up
+    /// to date as history changes, and dropped once the later edit's
+    /// revision is gc'ed.
+    pub fn delete_conflicts(&self) -> Vec<Interval> {
+        let head_chars = self.revs.last().unwrap().deletes_from_union.complement(self.union_str.len());
+        let mut intervals = Vec::new();
+        for &(_, ref conflicts) in &self.delete_conflicts {
+            let mut mapper = head_chars.mapper();
+            for &(beg, end) in conflicts._ranges() {
+                let start = mapper.doc_index_to_subset(beg);
+                intervals.push(Interval::new_closed_open(start, mapper.doc_index_to_subset(end)));
+            }
+        }
+        intervals
+    }
+
+    // The delta from the head to the content left by deleting `new_dels`
+    // from `new_union_str`, which is the union string with `new_inserts`
+    // added.
+    fn delta_from_head(&self, new_union_str: &Node<N>, new_inserts: &Subset, new_dels: &Subset) -> Delta<N> {
+        let head_dels = &self.revs.last().unwrap().deletes_from_union;
+        let head_dels = if new_inserts.is_empty() {
+            Cow::Borrowed(head_dels)
+        } else {
+            Cow::Owned(head_dels.transform_union(new_inserts))
+        };
+        Delta::synthesize(new_union_str, &head_dels, new_dels)
+    }
+
+    // The checksum of the content that `delta_from_head` leads to.
+    fn checksum_from_head(&self, delta_from_head: &Delta<N>) -> u64 {
+        delta_from_head.new_doc_hash(self.revs.last().unwrap().checksum, &self.text)
     }
 
+    // Move the recorded delete conflicts past the inserts of `rev`, which is
+    // about to be added to history, and record its own `conflicts`.
+    fn add_delete_conflicts(&mut self, rev: &Revision, conflicts: Subset) {
+        if let Edit { ref inserts, .. } = rev.edit {
+            if !inserts.is_empty() {
+                for entry in &mut self.delete_conflicts {
+                    entry.1 = entry.1.transform_expand(inserts);
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            self.delete_conflicts.push((rev.rev_id, conflicts));
+        }
+    }
+
+    // Also returns the regions of the edit's deletions that conflict with
+    // concurrent deletions, in the new union string, if the policy is to
+    // detect them.
     fn mk_new_rev(&self, new_priority: usize, undo_group: usize,
-            base_rev: usize, delta: Delta<RopeInfo>) -> (Revision, Rope) {
-        let ix = self.find_rev(base_rev).expect("base revision not found");
+            base_rev: usize, delta: Delta<N>) -> Result<(Revision, Node<N>, Subset), EngineError> {
+        let ix = self.find_rev(base_rev).ok_or(EngineError::MissingRevision(base_rev))?;
         let rev = &self.revs[ix];
-        let (ins_delta, deletes) = delta.factor();
+        #[cfg(test)]
+        let delta = if MALFORM_NEXT_EDIT.with(|malform| malform.replace(false)) {
+            let base_len = rev.deletes_from_union.len_after_delete(rev.union_str_len);
+            let (_, _, mut inserts) = delta.to_subsets();
+            Delta::simple_edit(Interval::new_closed_open(base_len + 5, base_len + 5), inserts.remove(0), base_len + 10)
+        } else {
+            delta
+        };
+        let (ins_delta, deletes, inserted) = delta.factor_full();
 
-        // rebase delta to be on the base_rev union instead of the text
-        let mut union_ins_delta = ins_delta.transform_expand(&rev.deletes_from_union, rev.union_str_len, true);
+        // rebase delta to be on the base_rev union instead of the text. An edit that
+        // inserts nothing leaves the union string alone, so then there is nothing to rebase.
+        let mut union_ins_delta = if inserted.is_empty() {
+            None
+        } else {
+            Some(ins_delta.transform_expand(&rev.deletes_from_union, rev.union_str_len, true))
+        };
         let mut new_deletes = deletes.transform_expand(&rev.deletes_from_union);
+        let detect = self.delete_policy == DeletePolicy::Detect;
+        let mut concurrent_deletes = Subset::default();
 
         // rebase the delta to be on the head union instead of the base_rev union
         for r in &self.revs[ix + 1..] {
-            if let Edit { priority, ref inserts, .. } = r.edit {
+            if let Edit { priority, ref inserts, ref deletes, .. } = r.edit {
+                if detect {
+                    concurrent_deletes = concurrent_deletes.transform_expand(inserts).union(deletes);
+                }
                 if !inserts.is_empty() {
-                    let after = new_priority >= priority;  // should never be ==
-                    union_ins_delta = union_ins_delta.transform_expand(inserts, r.union_str_len, after);
+                    let bias = if new_priority >= priority {  // should never be ==
+                        InsertBias::Right
+                    } else {
+                        InsertBias::Left
+                    };
+                    union_ins_delta = union_ins_delta.map(|d| d.transform_expand_biased(inserts, r.union_str_len, bias));
                     new_deletes = new_deletes.transform_expand(inserts);
                 }
             }
         }
 
         // rebase the deletion to be after the inserts instead of directly on the head union
-        let new_inserts = union_ins_delta.inserted_subset();
+        let new_inserts = union_ins_delta.as_ref().map_or_else(Subset::default, |d| d.inserted_subset());
         if !new_inserts.is_empty() {
             new_deletes = new_deletes.transform_expand(&new_inserts);
         }
 
-        let new_union_str = union_ins_delta.apply(&self.union_str);
+        let conflicts = if detect {
+            new_deletes.intersect(&concurrent_deletes.transform_expand(&new_inserts))
+        } else {
+            Subset::default()
+        };
+
+        let new_union_str = match union_ins_delta {
+            Some(union_ins_delta) => union_ins_delta.apply(&self.union_str),
+            None => self.union_str.clone(),
+        };
         // is the new edit in an undo group that was already undone due to concurrency?
         let undone = self.get_current_undo().map_or(false, |undos| undos.contains(&undo_group));
         let mut new_deletes_from_union = Cow::Borrowed(&self.revs.last().unwrap().deletes_from_union);
@@ -185,31 +870,255 @@ impl Engine {
                 new_deletes_from_union = Cow::Owned(new_deletes_from_union.union(&new_deletes));
             }
         }
-        (Revision {
+        let checksum = self.checksum_from_head(&self.delta_from_head(&new_union_str, &new_inserts,
+            &new_deletes_from_union));
+        Ok((Revision {
             rev_id: self.rev_id_counter,
             deletes_from_union: new_deletes_from_union.into_owned(),
             union_str_len: new_union_str.len(),
             edit: Edit {
                 priority: new_priority,
                 undo_group: undo_group,
+                base_rev: base_rev,
                 inserts: new_inserts,
                 deletes: new_deletes,
-            }
-        }, new_union_str)
+            },
+            meta: None,
+            author: None,
+            checksum: checksum,
+        }, new_union_str, conflicts))
     }
 
+    /// Apply an edit, made against `base_rev`, to the head revision.
+    ///
+    /// Returns `false`, without creating a new revision, if the edit turns
+    /// out to be a no-op (it neither inserts nor deletes anything once
+    /// rebased onto head). This keeps redundant edits out of the history.
+    ///
+    /// Panics if `base_rev` can't be found; see `try_edit_rev` for a version
+    /// that returns an error instead. If this panics (because of that, or
+    /// because the delta is malformed), the engine is left unchanged: the new
+    /// revision and union string are fully computed before any state is
+    /// modified.
     pub fn edit_rev(&mut self, priority: usize, undo_group: usize,
-            base_rev: usize, delta: Delta<RopeInfo>) {
-        let (new_rev, new_union_str) = self.mk_new_rev(priority, undo_group, base_rev, delta);
+            base_rev: usize, delta: Delta<N>) -> bool {
+        changed_or_panic(self.try_edit_rev(priority, undo_group, base_rev, delta))
+    }
+
+    /// Like `edit_rev`, but returns an error, leaving the engine unchanged,
+    /// if `base_rev` can't be found, for example because it has been gc'ed.
+    pub fn try_edit_rev(&mut self, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<N>) -> Result<bool, EngineError> {
+        match self.prepare_edit(priority, undo_group, base_rev, delta)? {
+            Some((new_rev, new_union_str, conflicts)) => {
+                self.commit_edit(undo_group, new_rev, new_union_str, conflicts);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Like `edit_rev`, but also returns what a view needs to update
+    /// incrementally: the new head revision id, the delta from the previous
+    /// head to the new one, and the new head's length. The delta is computed
+    /// from the new revision directly, which is cheaper than a separate
+    /// `delta_rev_head`. Panics if `base_rev` can't be found; see
+    /// `try_edit_rev_incremental`.
+    pub fn edit_rev_incremental(&mut self, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<N>) -> IncrementalUpdate<N> {
+        match self.try_edit_rev_incremental(priority, undo_group, base_rev, delta) {
+            Err(EngineError::Rejected) | Err(EngineError::TooLong) => self.unchanged_update(),
+            result => result.unwrap_or_else(|e| panic!("{}", e)),
+        }
+    }
+
+    /// Like `edit_rev_incremental`, but returns an error, leaving the engine
+    /// unchanged, if `base_rev` can't be found.
+    pub fn try_edit_rev_incremental(&mut self, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<N>) -> Result<IncrementalUpdate<N>, EngineError> {
+        let (new_rev, new_union_str, conflicts) = match self.prepare_edit(priority, undo_group, base_rev, delta)? {
+            Some(prepared) => prepared,
+            None => return Ok(self.unchanged_update()),
+        };
+        let head_delta = match new_rev.edit {
+            Edit { ref inserts, .. } => self.delta_from_head(&new_union_str, inserts, &new_rev.deletes_from_union),
+            Undo { .. } => unreachable!("prepare_edit makes edit revisions"),
+        };
+        self.commit_edit(undo_group, new_rev, new_union_str, conflicts);
+        Ok(IncrementalUpdate {
+            new_rev_id: self.get_head_rev_id(),
+            delta: head_delta,
+            new_head_len: self.text.len(),
+        })
+    }
+
+    fn unchanged_update(&self) -> IncrementalUpdate<N> {
+        IncrementalUpdate {
+            new_rev_id: self.get_head_rev_id(),
+            delta: Delta::simple_edit(Interval::new_closed_open(0, 0), Node::default(), self.text.len()),
+            new_head_len: self.text.len(),
+        }
+    }
+
+    // Check an edit against the edit filter and compute the revision it
+    // would make, returning `None` if it turns out to be a no-op.
+    fn prepare_edit(&self, priority: usize, undo_group: usize, base_rev: usize,
+            delta: Delta<N>) -> Result<Option<(Revision, Node<N>, Subset)>, EngineError> {
+        if let Some(ref filter) = self.edit_filter {
+            if !filter(&delta) {
+                return Err(EngineError::Rejected);
+            }
+        }
+        let (new_rev, new_union_str, conflicts) = self.mk_new_rev(priority, undo_group, base_rev, delta)?;
+        if let Edit { ref inserts, ref deletes, .. } = new_rev.edit {
+            if inserts.is_empty() && deletes.is_empty() {
+                return Ok(None);
+            }
+        }
+        if let Some(max_len) = self.max_len {
+            let new_len = new_rev.deletes_from_union.len_after_delete(new_union_str.len());
+            if new_len > max_len && new_len > self.text.len() {
+                return Err(EngineError::TooLong);
+            }
+        }
+        Ok(Some((new_rev, new_union_str, conflicts)))
+    }
+
+    // Make a revision computed by `prepare_edit` the new head.
+    fn commit_edit(&mut self, undo_group: usize, new_rev: Revision, new_union_str: Node<N>,
+            conflicts: Subset) {
+        let new_text = new_rev.deletes_from_union.delete_from(&new_union_str);
+        // Nothing past this point may fail, so the update is all-or-nothing.
         self.rev_id_counter += 1;
+        self.version += 1;
+        self.add_delete_conflicts(&new_rev, conflicts);
         self.revs.push(new_rev);
         self.union_str = new_union_str;
+        self.invalidate_union_cache();
+        self.text = new_text;
+        self.next_undo_group = std::cmp::max(self.next_undo_group, undo_group + 1);
+        if !self.keep_history {
+            self.compact_union();
+        }
+    }
+
+    /// Install a filter that is consulted before each edit, including
+    /// remote ones, is integrated. An edit for which it returns false is
+    /// dropped without changing the engine: `try_edit_rev` and the other
+    /// fallible methods return `EngineError::Rejected`, and `edit_rev` and
+    /// the other panicking methods return false. Replacing a previous filter
+    /// removes it.
+    pub fn set_edit_filter(&mut self, filter: Box<dyn Fn(&Delta<N>) -> bool + Send>) {
+        self.edit_filter = Some(filter);
+    }
+
+    /// Limit the length of the head: an edit, including a remote one, that
+    /// would make the head longer than `max` is dropped without changing the
+    /// engine, as with `set_edit_filter`, but with `EngineError::TooLong`.
+    /// Edits that don't lengthen the head, such as deletions, are always
+    /// allowed, even if the head is already longer than `max`.
+    pub fn set_max_len(&mut self, max: usize) {
+        self.max_len = Some(max);
+    }
+
+    /// Start a transaction: edits made through the returned guard all go
+    /// into one newly allocated undo group, greater than any group used so
+    /// far, so that they are undone together. Edits are applied as they are
+    /// made; dropping the guard ends the transaction.
+    pub fn begin_transaction(&mut self) -> TxnGuard<N> {
+        let undo_group = self.next_undo_group;
+        self.next_undo_group += 1;
+        TxnGuard {
+            engine: self,
+            undo_group: undo_group,
+        }
+    }
+
+    /// A delta that, when applied to the current head, results in the
+    /// content of `rev_id`.
+    pub fn delta_head_to_rev(&self, rev_id: usize) -> Result<Delta<N>, EngineError> {
+        let ix = self.find_rev(rev_id).ok_or(EngineError::MissingRevision(rev_id))?;
+        let head_rev = &self.revs.last().unwrap();
+        Ok(Delta::synthesize(&self.union_str, &head_rev.deletes_from_union,
+            &self.deletes_from_union_for_index(ix)))
+    }
+
+    /// Like `edit_rev`, but attaches a client-defined annotation to the new
+    /// revision, which can later be read back with `rev_meta`. Panics if
+    /// `base_rev` can't be found; see `try_edit_rev_with_meta`.
+    pub fn edit_rev_with_meta(&mut self, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<N>, meta: String) -> bool {
+        changed_or_panic(self.try_edit_rev_with_meta(priority, undo_group, base_rev, delta, meta))
+    }
+
+    /// Like `edit_rev_with_meta`, but returns an error if `base_rev` can't be
+    /// found.
+    pub fn try_edit_rev_with_meta(&mut self, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<N>, meta: String) -> Result<bool, EngineError> {
+        let changed = self.try_edit_rev(priority, undo_group, base_rev, delta)?;
+        if changed {
+            self.revs.last_mut().unwrap().meta = Some(meta);
+        }
+        Ok(changed)
+    }
+
+    /// Like `edit_rev`, but records `author` as the session that made the
+    /// edit, so that it can later be undone with `undo_author`. Panics if
+    /// `base_rev` can't be found; see `try_edit_rev_by`.
+    pub fn edit_rev_by(&mut self, author: SessionId, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<N>) -> bool {
+        changed_or_panic(self.try_edit_rev_by(author, priority, undo_group, base_rev, delta))
+    }
+
+    /// Like `edit_rev_by`, but returns an error if `base_rev` can't be found.
+    pub fn try_edit_rev_by(&mut self, author: SessionId, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<N>) -> Result<bool, EngineError> {
+        let changed = self.try_edit_rev(priority, undo_group, base_rev, delta)?;
+        if changed {
+            self.revs.last_mut().unwrap().author = Some(author);
+        }
+        Ok(changed)
+    }
+
+    /// Get the revision an edit was made against, if the revision can be
+    /// found and is an edit. Concurrent edits share a base revision, so this
+    /// exposes the history as a DAG.
+    pub fn rev_base(&self, rev_id: usize) -> Option<usize> {
+        self.find_rev(rev_id).and_then(|ix| match self.revs[ix].edit {
+            Edit { base_rev, .. } => Some(base_rev),
+            Undo { .. } => None,
+        })
+    }
+
+    /// Get the annotation attached to a revision, if the revision can be
+    /// found and has one.
+    pub fn rev_meta(&self, rev_id: usize) -> Option<&str> {
+        self.find_rev(rev_id).and_then(|ix| self.revs[ix].meta.as_ref().map(|meta| meta.as_str()))
     }
 
     // This computes undo all the way from the beginning. An optimization would be to not
     // recompute the prefix up to where the history diverges, but it's not clear that's
     // even worth the code complexity.
     fn compute_undo(&self, groups: BTreeSet<usize>) -> Revision {
+        let deletes_from_union = self.undo_deletes_from_union(&groups);
+        let checksum = self.checksum_from_head(&self.delta_from_head(&self.union_str, &Subset::default(),
+            &deletes_from_union));
+        Revision {
+            rev_id: self.rev_id_counter,
+            deletes_from_union: deletes_from_union,
+            union_str_len: self.union_str.len(),
+            edit: Undo {
+                groups: groups
+            },
+            meta: None,
+            author: None,
+            checksum: checksum,
+        }
+    }
+
+    // The deletions from the union string of the state in which exactly
+    // `groups` are undone.
+    fn undo_deletes_from_union(&self, groups: &BTreeSet<usize>) -> Subset {
         let mut deletes_from_union = Subset::default();
         for rev in &self.revs {
             if let Edit { ref undo_group, ref inserts, ref deletes, .. } = rev.edit {
@@ -227,48 +1136,479 @@ impl Engine {
                 }
             }
         }
-        Revision {
-            rev_id: self.rev_id_counter,
-            deletes_from_union: deletes_from_union,
-            union_str_len: self.union_str.len(),
-            edit: Undo {
-                groups: groups
-            }
-        }
+        deletes_from_union
     }
 
     pub fn undo(&mut self, groups: BTreeSet<usize>) {
+        if !self.keep_history {
+            return;
+        }
         let new_rev = self.compute_undo(groups);
+        self.text = new_rev.deletes_from_union.delete_from(&self.union_str);
         self.revs.push(new_rev);
+        self.invalidate_union_cache();
         self.rev_id_counter += 1;
+        self.version += 1;
     }
 
-    pub fn is_equivalent_revision(&self, base_rev: usize, other_rev: usize) -> bool {
-        let base_subset = self.find_rev(base_rev).map(|rev_index| self.deletes_from_union_for_index(rev_index));
-        let other_subset = self.find_rev(other_rev).map(|rev_index| self.deletes_from_union_for_index(rev_index));
-
-        base_subset.is_some() && base_subset == other_subset
+    /// The delta from the current head to the text that `undo(groups)` would
+    /// produce, without applying it, for example to show its effect before
+    /// asking for confirmation. The engine is not modified.
+    pub fn preview_undo(&self, groups: &BTreeSet<usize>) -> Delta<N> {
+        let head_dels = &self.revs.last().unwrap().deletes_from_union;
+        if !self.keep_history {
+            return Delta::synthesize(&self.union_str, head_dels, head_dels);
+        }
+        let new_dels = self.undo_deletes_from_union(groups);
+        Delta::synthesize(&self.union_str, head_dels, &new_dels)
     }
 
-    // Note: this function would need some work to handle retaining arbitrary revisions,
-    // partly because the reachability calculation would become more complicated (a
-    // revision might hold content from an undo group that would otherwise be gc'ed),
-    // and partly because you need to retain more undo history, to supply input to the
-    // reachability calculation.
-    //
-    // Thus, it's easiest to defer gc to when all plugins quiesce, but it's certainly
-    // possible to fix it so that's not necessary.
-    pub fn gc(&mut self, gc_groups: &BTreeSet<usize>) {
-        let mut gc_dels = Subset::default();
-        // TODO: want to let caller retain more rev_id's.
-        let mut retain_revs = BTreeSet::new();
-        if let Some(last) = self.revs.last() {
-            retain_revs.insert(last.rev_id);
+    /// Undo the `n` most recent edits that are not already undone, in
+    /// addition to whatever is currently undone. If there are fewer than `n`
+    /// such edits, all of them are undone. Returns the delta from the
+    /// previous head to the new head. If there is nothing to undo, for
+    /// example because `n` is 0, no revision is made and the delta is the
+    /// identity.
+    pub fn undo_last(&mut self, n: usize) -> Delta<N> {
+        let prev_head_rev_id = self.get_head_rev_id();
+        let mut groups = self.get_current_undo().cloned().unwrap_or_default();
+        let mut count = 0;
+        for rev in self.revs.iter().rev() {
+            if count == n {
+                break;
+            }
+            if let Edit { undo_group, .. } = rev.edit {
+                if groups.insert(undo_group) {
+                    count += 1;
+                }
+            }
         }
-        {
-            let cur_undo = self.get_current_undo();
-            for rev in &self.revs {
-                if let Edit { ref undo_group, ref inserts, ref deletes, .. } = rev.edit {
+        if count == 0 {
+            return Delta::simple_edit(Interval::new_closed_open(0, 0), Node::default(), self.text.len());
+        }
+        self.undo(groups);
+        self.delta_rev_head(prev_head_rev_id)
+    }
+
+    /// Reconstruct the delta that an edit revision originally applied to the
+    /// text of its base revision.
+    fn edit_delta_for_index(&self, rev_index: usize) -> Result<Delta<N>, EngineError> {
+        let rev = &self.revs[rev_index];
+        let (base_rev, inserts, deletes) = match rev.edit {
+            Edit { base_rev, ref inserts, ref deletes, .. } => (base_rev, inserts, deletes),
+            Undo { .. } => return Err(EngineError::NotAnEdit(rev.rev_id)),
+        };
+        let base_index = self.find_rev(base_rev).ok_or(EngineError::MissingRevision(base_rev))?;
+
+        // Bring the edit's inserts and deletes forward to the head union, and
+        // compute the base revision's deletions as though this edit were the
+        // only one made since.
+        let mut ins_from_union = Cow::Borrowed(inserts);
+        let mut dels_from_union = Cow::Borrowed(deletes);
+        for r in &self.revs[rev_index + 1..] {
+            if let Edit { inserts: ref later_inserts, .. } = r.edit {
+                if !later_inserts.is_empty() {
+                    ins_from_union = Cow::Owned(ins_from_union.transform_expand(later_inserts));
+                    dels_from_union = Cow::Owned(dels_from_union.transform_expand(later_inserts));
+                }
+            }
+        }
+        let mut base_from_union = Cow::Borrowed(&self.revs[base_index].deletes_from_union);
+        for (i, r) in self.revs.iter().enumerate().skip(base_index + 1) {
+            if let Edit { inserts: ref later_inserts, .. } = r.edit {
+                if !later_inserts.is_empty() {
+                    base_from_union = Cow::Owned(if i == rev_index {
+                        base_from_union.transform_expand(later_inserts)
+                    } else {
+                        base_from_union.transform_union(later_inserts)
+                    });
+                }
+            }
+        }
+        let old_dels = base_from_union.union(&ins_from_union);
+        let new_dels = base_from_union.union(&dels_from_union);
+        Ok(Delta::synthesize(&self.union_str, &old_dels, &new_dels))
+    }
+
+    /// Change the priority of an edit revision, and recompute the history
+    /// from that revision onward as though it had been made with the new
+    /// priority. This changes how the edit is ordered with respect to
+    /// concurrent edits. It is expensive, as every later revision is
+    /// replayed.
+    pub fn reprioritize(&mut self, rev_id: usize, new_priority: usize) -> Result<(), EngineError> {
+        let ix = self.find_rev(rev_id).ok_or(EngineError::MissingRevision(rev_id))?;
+        if let Undo { .. } = self.revs[ix].edit {
+            return Err(EngineError::NotAnEdit(rev_id));
+        }
+        let (mut replay, later_inserts) = self.replay_steps_from(ix)?;
+        if let ReplayStep::Edit(ref mut priority, ..) = replay[0].step {
+            *priority = new_priority;
+        }
+        self.rebuild_from(ix, &later_inserts, replay);
+        Ok(())
+    }
+
+    /// Squash the consecutive edit revisions from `from_rev` to `to_rev`
+    /// into a single edit revision in `undo_group`, with the same effect on
+    /// the text, so that they are undone together. The new revision takes
+    /// `to_rev`'s id, and the ids of the others are no longer found. Like
+    /// `reprioritize`, this rewrites history and is expensive, as every
+    /// later revision is replayed.
+    ///
+    /// The squashed revisions must all be edits (`NotAnEdit` otherwise, for
+    /// example if an undo falls between them), and must form a linear
+    /// chain: each one after the first must be based on the one before it,
+    /// none may be currently undone, no later revision may be based on one
+    /// of them other than `to_rev`, and no later undo may involve their undo
+    /// groups (it would have nothing to refer to once they are replaced). If
+    /// any of these doesn't hold, this returns `CannotSquash` with the
+    /// offending revision.
+    ///
+    /// The new revision keeps the meta and author of the squashed revisions
+    /// if they all agree, and has none otherwise.
+    pub fn squash(&mut self, from_rev: usize, to_rev: usize, undo_group: usize) -> Result<(), EngineError> {
+        let from_ix = self.find_rev(from_rev).ok_or(EngineError::MissingRevision(from_rev))?;
+        let to_ix = self.find_rev(to_rev).ok_or(EngineError::MissingRevision(to_rev))?;
+        if to_ix < from_ix {
+            return Err(EngineError::CannotSquash(to_rev));
+        }
+        let undone = self.get_current_undo().cloned().unwrap_or_default();
+        let mut squashed_groups = BTreeSet::new();
+        for i in from_ix..to_ix + 1 {
+            let rev = &self.revs[i];
+            match rev.edit {
+                Edit { undo_group, base_rev, .. } => {
+                    if undone.contains(&undo_group) || (i > from_ix && base_rev != self.revs[i - 1].rev_id) {
+                        return Err(EngineError::CannotSquash(rev.rev_id));
+                    }
+                    squashed_groups.insert(undo_group);
+                }
+                Undo { .. } => return Err(EngineError::NotAnEdit(rev.rev_id)),
+            }
+        }
+        for rev in &self.revs[to_ix + 1..] {
+            let conflicts = match rev.edit {
+                Edit { base_rev, .. } => {
+                    base_rev != to_rev && self.revs[from_ix..to_ix].iter().any(|r| r.rev_id == base_rev)
+                }
+                Undo { ref groups } => !groups.is_disjoint(&squashed_groups),
+            };
+            if conflicts {
+                return Err(EngineError::CannotSquash(rev.rev_id));
+            }
+        }
+
+        let (mut replay, later_inserts) = self.replay_steps_from(from_ix)?;
+        let later = replay.split_off(to_ix + 1 - from_ix);
+        let mut squashed: Option<(usize, usize, Delta<N>)> = None;
+        let mut author = replay[0].author;
+        let mut meta = replay[0].meta.clone();
+        for entry in replay {
+            if entry.author != author {
+                author = None;
+            }
+            if entry.meta != meta {
+                meta = None;
+            }
+            if let ReplayStep::Edit(priority, _, base_rev, delta) = entry.step {
+                squashed = Some(match squashed {
+                    None => (priority, base_rev, delta),
+                    Some((priority, base_rev, acc)) => (priority, base_rev, acc.compose(&delta)),
+                });
+            }
+        }
+        let (priority, base_rev, delta) = squashed.expect("the squashed range is non-empty");
+        let mut replay = vec![ReplayEntry {
+            rev_id: to_rev,
+            meta: meta,
+            author: author,
+            step: ReplayStep::Edit(priority, undo_group, base_rev, delta),
+        }];
+        replay.extend(later);
+        self.rebuild_from(from_ix, &later_inserts, replay);
+        self.next_undo_group = std::cmp::max(self.next_undo_group, undo_group + 1);
+        Ok(())
+    }
+
+    // Reconstruct what is needed to replay the revisions from index `ix`
+    // onward, along with their inserts, before anything is mutated.
+    fn replay_steps_from(&self, ix: usize) -> Result<(Vec<ReplayEntry<N>>, Subset), EngineError> {
+        let mut replay = Vec::new();
+        let mut later_inserts = Subset::default();
+        for i in ix..self.revs.len() {
+            let rev = &self.revs[i];
+            let step = match rev.edit {
+                Edit { priority, undo_group, base_rev, ref inserts, .. } => {
+                    let delta = self.edit_delta_for_index(i)?;
+                    if !inserts.is_empty() {
+                        later_inserts = later_inserts.transform_union(inserts);
+                    }
+                    ReplayStep::Edit(priority, undo_group, base_rev, delta)
+                }
+                Undo { ref groups } => ReplayStep::Undo(groups.clone()),
+            };
+            replay.push(ReplayEntry {
+                rev_id: rev.rev_id,
+                meta: rev.meta.clone(),
+                author: rev.author,
+                step: step,
+            });
+        }
+        Ok((replay, later_inserts))
+    }
+
+    // Drop the revisions from index `ix` onward, whose inserts are
+    // `later_inserts`, and recompute history by replaying `replay` in their
+    // place.
+    fn rebuild_from(&mut self, ix: usize, later_inserts: &Subset, replay: Vec<ReplayEntry<N>>) {
+        let rev_id_counter = self.rev_id_counter;
+        self.union_str = later_inserts.delete_from(&self.union_str);
+        let kept_ids: BTreeSet<usize> = self.revs[..ix].iter().map(|rev| rev.rev_id).collect();
+        let old_conflicts = std::mem::replace(&mut self.delete_conflicts, Vec::new());
+        for (rev_id, conflicts) in old_conflicts {
+            if kept_ids.contains(&rev_id) {
+                self.delete_conflicts.push((rev_id, later_inserts.transform_shrink(&conflicts)));
+            }
+        }
+        self.revs.truncate(ix);
+        self.invalidate_union_cache();
+        // Replayed revisions derive their checksums from the head text.
+        self.text = self.rev_content_for_index(ix - 1);
+        for entry in replay {
+            let mut new_rev = match entry.step {
+                ReplayStep::Edit(priority, undo_group, base_rev, delta) => {
+                    // The base revision precedes the edit and was found by
+                    // `edit_delta_for_index`, so it is still present.
+                    let (mut new_rev, new_union_str, conflicts) = self.mk_new_rev(priority, undo_group, base_rev, delta)
+                        .expect("base revision was found before replay");
+                    new_rev.rev_id = entry.rev_id;
+                    self.add_delete_conflicts(&new_rev, conflicts);
+                    self.union_str = new_union_str;
+                    new_rev
+                }
+                ReplayStep::Undo(groups) => self.compute_undo(groups),
+            };
+            new_rev.rev_id = entry.rev_id;
+            new_rev.meta = entry.meta;
+            new_rev.author = entry.author;
+            self.text = new_rev.deletes_from_union.delete_from(&self.union_str);
+            self.revs.push(new_rev);
+            self.invalidate_union_cache();
+        }
+        self.rev_id_counter = rev_id_counter;
+        self.version += 1;
+    }
+
+    /// The undo groups of the edits in history that are currently undone.
+    /// These edits contribute nothing to the head revision, so the groups
+    /// are good candidates to pass to `gc`.
+    pub fn fully_undone_groups(&self) -> BTreeSet<usize> {
+        let mut result = BTreeSet::new();
+        if let Some(undos) = self.get_current_undo() {
+            for rev in &self.revs {
+                if let Edit { undo_group, .. } = rev.edit {
+                    if undos.contains(&undo_group) {
+                        result.insert(undo_group);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Whether there is an edit in history that is not currently undone,
+    /// that is, whether `undo_last` would change the head.
+    pub fn can_undo(&self) -> bool {
+        let undone = self.get_current_undo();
+        self.revs.iter().any(|rev| match rev.edit {
+            Edit { undo_group, .. } => !undone.map_or(false, |undos| undos.contains(&undo_group)),
+            Undo { .. } => false,
+        })
+    }
+
+    /// Whether there is an undone edit in history that could be redone by
+    /// removing its group from the undo set.
+    pub fn can_redo(&self) -> bool {
+        !self.fully_undone_groups().is_empty()
+    }
+
+    /// The number of distinct authors with edits that are present in the
+    /// head revision, that is, edits whose undo group is not undone. Edits
+    /// with no recorded author are not counted.
+    pub fn head_author_count(&self) -> usize {
+        let undone = self.get_current_undo();
+        let mut authors = BTreeSet::new();
+        for rev in &self.revs {
+            if let Edit { undo_group, .. } = rev.edit {
+                if !undone.map_or(false, |undos| undos.contains(&undo_group)) {
+                    if let Some(author) = rev.author {
+                        authors.insert(author);
+                    }
+                }
+            }
+        }
+        authors.len()
+    }
+
+    /// Undo every edit authored by `author`, in addition to whatever is
+    /// currently undone, leaving other sessions' edits intact. For this to
+    /// undo only that session's edits, sessions must not share undo groups.
+    /// Returns the delta from the previous head to the new head.
+    pub fn undo_author(&mut self, author: SessionId) -> Delta<N> {
+        let prev_head_rev_id = self.get_head_rev_id();
+        let mut groups = self.get_current_undo().cloned().unwrap_or_default();
+        for rev in &self.revs {
+            if let Edit { undo_group, .. } = rev.edit {
+                if rev.author == Some(author) {
+                    groups.insert(undo_group);
+                }
+            }
+        }
+        self.undo(groups);
+        self.delta_rev_head(prev_head_rev_id)
+    }
+
+    /// For each author with edits made after `since_rev`, the bounding
+    /// interval in the head of the text those edits inserted or deleted, for
+    /// showing where each session is editing. Text that has since been
+    /// deleted collapses to its position in the head. Edits with no recorded
+    /// author are skipped. The result is sorted by author.
+    pub fn author_activity(&self, since_rev: usize) -> Result<Vec<(SessionId, Interval)>, EngineError> {
+        let since_ix = self.find_rev(since_rev).ok_or(EngineError::MissingRevision(since_rev))?;
+        // Bounds in the current union string, found back to front so later
+        // inserts are accumulated once, as in `history_cursor`.
+        let mut bounds: BTreeMap<SessionId, (usize, usize)> = BTreeMap::new();
+        let mut later_inserts = Subset::default();
+        for rev in self.revs[since_ix + 1..].iter().rev() {
+            if let Edit { ref inserts, ref deletes, .. } = rev.edit {
+                if let Some(author) = rev.author {
+                    let changed = inserts.transform_expand(&later_inserts)
+                        .union(&deletes.transform_expand(&later_inserts));
+                    if let (Some(&(lo, _)), Some(&(_, hi))) = (changed._ranges().first(), changed._ranges().last()) {
+                        let entry = bounds.entry(author).or_insert((lo, hi));
+                        *entry = (std::cmp::min(entry.0, lo), std::cmp::max(entry.1, hi));
+                    }
+                }
+                if !inserts.is_empty() {
+                    later_inserts = inserts.transform_union(&later_inserts);
+                }
+            }
+        }
+        let head_chars = self.revs.last().unwrap().deletes_from_union.complement(self.union_str.len());
+        Ok(bounds.into_iter().map(|(author, (lo, hi))| {
+            let mut mapper = head_chars.mapper();
+            let start = mapper.doc_index_to_subset(lo);
+            (author, Interval::new_closed_open(start, mapper.doc_index_to_subset(hi)))
+        }).collect())
+    }
+
+    /// Get a cursor over the revisions in history, positioned at the oldest
+    /// one, for scrubbing through the document's history.
+    pub fn history_cursor(&self) -> HistoryCursor<N> {
+        // Build each revision's deletes from the current union string back
+        // to front, so the later inserts are accumulated once in total
+        // rather than once per revision.
+        let mut deletes = Vec::with_capacity(self.revs.len());
+        let mut later_inserts = Subset::default();
+        for rev in self.revs.iter().rev() {
+            deletes.push(rev.deletes_from_union.transform_union(&later_inserts));
+            if let Edit { ref inserts, .. } = rev.edit {
+                if !inserts.is_empty() {
+                    later_inserts = inserts.transform_union(&later_inserts);
+                }
+            }
+        }
+        deletes.reverse();
+        HistoryCursor {
+            engine: self,
+            deletes: deletes,
+            ix: 0,
+        }
+    }
+
+    /// Determine whether the head's content is the same as the initial
+    /// contents given to `new` (or `reset_contents`), for example because
+    /// every edit has been undone. Content that was reached some other way,
+    /// such as by typing text and deleting it again, is detected by
+    /// comparing it with the initial contents, which are kept for this; the
+    /// checksums are compared first, so that usually only a pristine head
+    /// is compared in full.
+    pub fn is_pristine(&self) -> bool {
+        let head_rev_id = self.get_head_rev_id();
+        self.is_equivalent_revision(self.genesis_rev_id, head_rev_id)
+            || (self.revs.last().unwrap().checksum == self.genesis_checksum
+                && same_content(&self.text, &self.genesis_text))
+    }
+
+    pub fn is_equivalent_revision(&self, base_rev: usize, other_rev: usize) -> bool {
+        let base_subset = self.find_rev(base_rev).map(|rev_index| self.deletes_from_union_for_index(rev_index));
+        let other_subset = self.find_rev(other_rev).map(|rev_index| self.deletes_from_union_for_index(rev_index));
+
+        base_subset.is_some() && base_subset == other_subset
+    }
+
+    /// Register a plugin that may refer to revisions in history. Until the
+    /// handle is passed to `unregister_plugin`, `flush_gc` will defer.
+    pub fn register_plugin(&mut self) -> PluginHandle {
+        let id = self.next_plugin_id;
+        self.next_plugin_id += 1;
+        self.plugins.insert(id);
+        PluginHandle(id)
+    }
+
+    /// Unregister a plugin, once it no longer refers to revisions.
+    pub fn unregister_plugin(&mut self, handle: PluginHandle) {
+        self.plugins.remove(&handle.0);
+    }
+
+    /// Garbage collect `gc_groups`, along with any groups deferred by earlier
+    /// calls, if no plugins are registered. Otherwise, nothing is collected
+    /// and the groups are remembered for the next call, so that gc never
+    /// strands revisions a plugin still needs.
+    pub fn flush_gc(&mut self, gc_groups: &BTreeSet<usize>) -> GcStatus {
+        self.pending_gc_groups.extend(gc_groups.iter().cloned());
+        if !self.plugins.is_empty() {
+            return GcStatus::Deferred;
+        }
+        let groups = std::mem::replace(&mut self.pending_gc_groups, BTreeSet::new());
+        self.gc(&groups);
+        GcStatus::Collected
+    }
+
+    /// Shrink the union string as far as possible, by gc'ing every undo
+    /// group in history. Afterwards the union string holds only the head
+    /// text and the tombstones the head revision still refers to. This is a
+    /// heavy maintenance operation, and it discards the ability to undo or
+    /// redo any edit made so far.
+    pub fn compact_union(&mut self) {
+        let mut groups = BTreeSet::new();
+        for rev in &self.revs {
+            if let Edit { undo_group, .. } = rev.edit {
+                groups.insert(undo_group);
+            }
+        }
+        self.gc(&groups);
+    }
+
+    // Note: this function would need some work to handle retaining arbitrary revisions,
+    // partly because the reachability calculation would become more complicated (a
+    // revision might hold content from an undo group that would otherwise be gc'ed),
+    // and partly because you need to retain more undo history, to supply input to the
+    // reachability calculation.
+    //
+    // Thus, it's easiest to defer gc to when all plugins quiesce (which is what
+    // `flush_gc` does), but it's certainly possible to fix it so that's not necessary.
+    pub fn gc(&mut self, gc_groups: &BTreeSet<usize>) {
+        let mut gc_dels = Subset::default();
+        // TODO: want to let caller retain more rev_id's.
+        let mut retain_revs = BTreeSet::new();
+        if let Some(last) = self.revs.last() {
+            retain_revs.insert(last.rev_id);
+        }
+        {
+            let cur_undo = self.get_current_undo();
+            for rev in &self.revs {
+                if let Edit { ref undo_group, ref inserts, ref deletes, .. } = rev.edit {
                     if !retain_revs.contains(&rev.rev_id) && gc_groups.contains(undo_group) {
                         if cur_undo.map_or(false, |undos| undos.contains(undo_group)) {
                             if !inserts.is_empty() {
@@ -291,10 +1631,13 @@ impl Engine {
         if !gc_dels.is_empty() {
             self.union_str = gc_dels.delete_from(&self.union_str);
         }
+        // The loop below shrinks `gc_dels` back to older revisions, but the
+        // delete conflicts are in the head union string.
+        let head_gc_dels = gc_dels.clone();
         let old_revs = std::mem::replace(&mut self.revs, Vec::new());
         for rev in old_revs.into_iter().rev() {
             match rev.edit {
-                Edit { priority, undo_group, inserts, deletes } => {
+                Edit { priority, undo_group, base_rev, inserts, deletes } => {
                     let new_gc_dels = if inserts.is_empty() {
                         None
                     } else {
@@ -316,9 +1659,13 @@ impl Engine {
                             edit: Edit {
                                 priority: priority,
                                 undo_group: undo_group,
+                                base_rev: base_rev,
                                 inserts: inserts,
                                 deletes: deletes,
-                            }
+                            },
+                            meta: rev.meta,
+                            author: rev.author,
+                            checksum: rev.checksum,
                         });
                     }
                     if let Some(new_gc_dels) = new_gc_dels {
@@ -328,6 +1675,8 @@ impl Engine {
                 Undo { groups } => {
                     // We're super-aggressive about dropping these; after gc, the history
                     // of which undos were used to compute deletes_from_union in edits may be lost.
+                    // Since only retained undos survive, neither do runs of consecutive undos,
+                    // of which only the latest matters.
                     if retain_revs.contains(&rev.rev_id) {
                         let (deletes_from_union, len) = if gc_dels.is_empty() {
                             (rev.deletes_from_union, rev.union_str_len)
@@ -341,23 +1690,176 @@ impl Engine {
                             union_str_len: len,
                             edit: Undo {
                                 groups: &groups - gc_groups,
-                            }
+                            },
+                            meta: rev.meta,
+                            author: rev.author,
+                            checksum: rev.checksum,
                         })
                     }
                 }
             }
         }
         self.revs.reverse();
+        let old_conflicts = std::mem::replace(&mut self.delete_conflicts, Vec::new());
+        for (rev_id, conflicts) in old_conflicts {
+            if self.find_rev(rev_id).is_some() {
+                let conflicts = head_gc_dels.transform_shrink(&conflicts);
+                if !conflicts.is_empty() {
+                    self.delete_conflicts.push((rev_id, conflicts));
+                }
+            }
+        }
+        self.invalidate_union_cache();
+        self.version += 1;
+    }
+}
+
+/// An `Engine` over the built-in `Rope`.
+pub type RopeEngine = Engine<RopeInfo>;
+
+impl Engine<RopeInfo> {
+    /// Apply an edit received from another peer. This is like `try_edit_rev`,
+    /// but first checks that the delta's inserted ropes are well-formed,
+    /// returning `EngineError::MalformedInsert`, without changing the engine,
+    /// if they are not.
+    pub fn apply_remote(&mut self, priority: usize, undo_group: usize,
+            base_rev: usize, delta: Delta<RopeInfo>) -> Result<bool, EngineError> {
+        if !delta.has_well_formed_inserts() {
+            return Err(EngineError::MalformedInsert);
+        }
+        self.try_edit_rev(priority, undo_group, base_rev, delta)
+    }
+
+    /// Make `target` the new head text, as `set_head_from_content` does.
+    /// Returns `Ok(false)` if `target` is the same as the head.
+    pub fn set_head_to(&mut self, priority: usize, undo_group: usize,
+            target: Rope) -> Result<bool, EngineError> {
+        self.set_head_from_content(priority, undo_group, target).map(|delta| !delta.is_identity())
+    }
+
+    /// Revert the document to the content of `rev_id`, as a new edit on top
+    /// of head rather than a rewind of history, so the revert can itself be
+    /// undone; see `set_head_from_content`. Returns an error, leaving the
+    /// engine unchanged, if `rev_id` can't be found or the edit is rejected.
+    pub fn revert_to(&mut self, priority: usize, undo_group: usize,
+            rev_id: usize) -> Result<Delta<RopeInfo>, EngineError> {
+        let content = self.get_rev(rev_id).ok_or(EngineError::MissingRevision(rev_id))?;
+        self.set_head_from_content(priority, undo_group, content)
+    }
+
+    /// Make `content` the new head text, as an edit against the current head
+    /// computed with `Delta::from_diff`, so that it can be undone like any
+    /// other edit. The content can be anything, such as an old revision or a
+    /// merge of two revisions. Returns the delta from the previous head to
+    /// the new one, or an error, leaving the engine unchanged, if the edit is
+    /// rejected.
+    pub fn set_head_from_content(&mut self, priority: usize, undo_group: usize,
+            content: Rope) -> Result<Delta<RopeInfo>, EngineError> {
+        let delta = Delta::from_diff(&self.text, &content);
+        let head_rev_id = self.get_head_rev_id();
+        self.try_edit_rev(priority, undo_group, head_rev_id, delta.clone())?;
+        Ok(delta)
+    }
+}
+
+/// A cursor over the revisions of an `Engine`, created by
+/// `Engine::history_cursor`. Moving the cursor with `next` or `prev` yields
+/// the id and contents of the revision moved to, along with the delta from
+/// the revision moved from.
+pub struct HistoryCursor<'a, N: NodeInfo + 'a> {
+    engine: &'a Engine<N>,
+    // The deletes from the engine's union string for each revision.
+    deletes: Vec<Subset>,
+    ix: usize,
+}
+
+impl<'a, N: NodeInfo + 'a> HistoryCursor<'a, N> where N::L: AsRef<[u8]> {
+    /// The id of the revision the cursor is at.
+    pub fn rev_id(&self) -> usize {
+        self.engine.revs[self.ix].rev_id
+    }
+
+    /// Move to the previous revision, if there is one.
+    pub fn prev(&mut self) -> Option<(usize, Node<N>, Delta<N>)> {
+        if self.ix == 0 {
+            return None;
+        }
+        self.ix -= 1;
+        Some(self.step_from(self.ix + 1))
+    }
+
+    fn step_from(&self, from_ix: usize) -> (usize, Node<N>, Delta<N>) {
+        let union_str = &self.engine.union_str;
+        let deletes = &self.deletes[self.ix];
+        let delta = Delta::synthesize(union_str, &self.deletes[from_ix], deletes);
+        (self.rev_id(), deletes.delete_from(union_str), delta)
+    }
+}
+
+impl<'a, N: NodeInfo + 'a> Iterator for HistoryCursor<'a, N> where N::L: AsRef<[u8]> {
+    type Item = (usize, Node<N>, Delta<N>);
+
+    /// Move to the next revision, if there is one.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ix + 1 >= self.deletes.len() {
+            return None;
+        }
+        self.ix += 1;
+        Some(self.step_from(self.ix - 1))
+    }
+}
+
+/// A transaction on an `Engine`, created by `Engine::begin_transaction`.
+pub struct TxnGuard<'a, N: NodeInfo + 'a> {
+    engine: &'a mut Engine<N>,
+    undo_group: usize,
+}
+
+impl<'a, N: NodeInfo + 'a> TxnGuard<'a, N> where N::L: AsRef<[u8]> {
+    /// The undo group that the transaction's edits go into.
+    pub fn undo_group(&self) -> usize {
+        self.undo_group
+    }
+
+    /// The engine, for reading its state during the transaction.
+    pub fn engine(&self) -> &Engine<N> {
+        self.engine
+    }
+
+    /// Like `Engine::edit_rev`, with the transaction's undo group.
+    pub fn edit_rev(&mut self, priority: usize, base_rev: usize, delta: Delta<N>) -> bool {
+        self.engine.edit_rev(priority, self.undo_group, base_rev, delta)
+    }
+
+    /// Like `Engine::try_edit_rev`, with the transaction's undo group.
+    pub fn try_edit_rev(&mut self, priority: usize, base_rev: usize,
+            delta: Delta<N>) -> Result<bool, EngineError> {
+        self.engine.try_edit_rev(priority, self.undo_group, base_rev, delta)
+    }
+
+    /// Start a nested transaction. It shares this transaction's undo group,
+    /// so its edits are undone along with the outermost transaction's.
+    pub fn begin_transaction(&mut self) -> TxnGuard<N> {
+        TxnGuard {
+            engine: self.engine,
+            undo_group: self.undo_group,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use engine::Engine;
+    use engine::{content_checksum, CatchUp, DeletePolicy, Engine, EngineError, EngineOp, GcStatus,
+        IncrementalUpdate, UnionDeletes, MALFORM_NEXT_EDIT, UNION_FOLDS};
+    use engine::Contents::*;
     use rope::{Rope, RopeInfo};
     use delta::{Builder, Delta};
     use interval::Interval;
+    use subset::SubsetBuilder;
+    use tree::{Cursor, Leaf, Node, NodeInfo};
     use std::collections::BTreeSet;
+    use std::panic;
+    use std::sync::Arc;
 
     const TEST_STR: &'static str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 
@@ -443,4 +1945,1103 @@ mod tests {
         let d = engine.delta_rev_head(1);
         assert_eq!(String::from(engine.get_head()), d.apply_to_string("0123456789abcDEEFghijklmnopqr999stuvz"));
     }
+
+    #[test]
+    fn undo_last() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("a"), TEST_STR.len()));
+        engine.edit_rev(1, 2, 1, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("b"), TEST_STR.len() + 1));
+        engine.edit_rev(1, 3, 2, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("c"), TEST_STR.len() + 2));
+        assert_eq!(format!("cba{}", TEST_STR), String::from(engine.get_head()));
+        let head_rev_id = engine.get_head_rev_id();
+        assert!(engine.undo_last(0).is_identity());
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+        let d = engine.undo_last(2);
+        assert_eq!(format!("a{}", TEST_STR), String::from(engine.get_head()));
+        assert_eq!(format!("a{}", TEST_STR), d.apply_to_string(&format!("cba{}", TEST_STR)));
+        engine.undo_last(5);
+        assert_eq!(TEST_STR, String::from(engine.get_head()));
+        let head_rev_id = engine.get_head_rev_id();
+        assert!(engine.undo_last(1).is_identity());
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+    }
+
+    #[test]
+    fn deltas_since() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        let rev_2_len = engine.get_rev(2).unwrap().len();
+        engine.edit_rev(1, 2, 2, Delta::simple_edit(Interval::new_closed_open(3, 8), Rope::from("-"), rev_2_len));
+        let deltas = engine.deltas_since(0).unwrap();
+        assert_eq!(vec![1, 2, 3], deltas.iter().map(|&(rev_id, _)| rev_id).collect::<Vec<_>>());
+        let mut text = String::from(TEST_STR);
+        for (rev_id, delta) in deltas {
+            text = delta.apply_to_string(&text);
+            assert_eq!(String::from(engine.get_rev(rev_id).unwrap()), text);
+        }
+        assert_eq!(String::from(engine.get_head()), text);
+        assert!(engine.deltas_since(42).is_none());
+    }
+
+    #[test]
+    fn reprioritize() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from("a"), TEST_STR.len());
+        let d2 = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from("b"), TEST_STR.len());
+        engine.edit_rev(1, 1, 0, d1);
+        engine.edit_rev(2, 2, 0, d2);
+        engine.edit_rev(2, 3, 2, Delta::simple_edit(Interval::new_closed_open(0, 1), Rope::from(""), TEST_STR.len() + 2));
+        assert_eq!("1234ab56789", &String::from(engine.get_head())[..11]);
+        assert_eq!(Ok(()), engine.reprioritize(1, 3));
+        assert_eq!("1234ba56789", &String::from(engine.get_head())[..11]);
+        assert_eq!(3, engine.get_head_rev_id());
+        assert_eq!("01234ba56789", &String::from(engine.get_rev(2).unwrap())[..12]);
+        assert_eq!("01234a56789", &String::from(engine.get_rev(1).unwrap())[..11]);
+        assert_eq!(Err(EngineError::MissingRevision(42)), engine.reprioritize(42, 0));
+        assert_eq!(Err(EngineError::NotAnEdit(0)), engine.reprioritize(0, 0));
+    }
+
+    #[test]
+    fn rev_meta() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev_with_meta(1, 1, 0, build_delta_1(), "delete-word".to_owned());
+        engine.edit_rev(1, 2, 1, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("!"), 37));
+        assert_eq!(Some("delete-word"), engine.rev_meta(1));
+        assert_eq!(None, engine.rev_meta(2));
+        assert_eq!(None, engine.rev_meta(42));
+        engine.gc(&[2].iter().cloned().collect());
+        assert_eq!(Some("delete-word"), engine.rev_meta(1));
+    }
+
+    #[test]
+    fn rev_union_len() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert_eq!(Some(TEST_STR.len()), engine.rev_union_len(0));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        // build_delta_1 inserts "DEEF" and "999"; deletions stay in the union
+        assert_eq!(Some(TEST_STR.len() + 7), engine.rev_union_len(1));
+        assert_eq!(None, engine.rev_union_len(2));
+    }
+
+    #[test]
+    fn edit_rev_panic_leaves_engine_unchanged() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        let head = String::from(engine.get_head());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            engine.edit_rev(1, 1, 42, build_delta_2());
+        }));
+        assert!(result.is_err());
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(1, engine.get_head_rev_id());
+        assert_eq!(Some(TEST_STR.len() + 7), engine.rev_union_len(1));
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        assert_eq!("0!3456789abcDEEFGIjklmnopqr888999stuvHIz", String::from(engine.get_head()));
+        assert_eq!(2, engine.get_head_rev_id());
+    }
+
+    #[test]
+    fn malformed_edit_leaves_engine_unchanged() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        let head = String::from(engine.get_head());
+        let union_len = engine.union_len();
+        MALFORM_NEXT_EDIT.with(|malform| malform.set(true));
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            engine.edit_rev(0, 1, 0, build_delta_2());
+        }));
+        assert!(result.is_err());
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(1, engine.get_head_rev_id());
+        assert_eq!(union_len, engine.union_len());
+        assert_eq!(Ok(()), engine.check_invariants());
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        assert_eq!("0!3456789abcDEEFGIjklmnopqr888999stuvHIz", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn fully_undone_groups() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        assert!(engine.fully_undone_groups().is_empty());
+        // group 5 has no edits, so it isn't reported
+        engine.undo([1, 5].iter().cloned().collect());
+        let expected: BTreeSet<usize> = [1].iter().cloned().collect();
+        assert_eq!(expected, engine.fully_undone_groups());
+        engine.gc(&engine.fully_undone_groups());
+        assert_eq!("0123456789abcDEEFghijklmnopqr999stuvz", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn edit_rev_noop() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert!(engine.edit_rev(1, 0, 0, build_delta_1()));
+        let head = String::from(engine.get_head());
+        let identity = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from(""), head.len());
+        assert!(!engine.edit_rev(1, 1, 1, identity));
+        assert_eq!(1, engine.get_head_rev_id());
+        assert_eq!(head, String::from(engine.get_head()));
+        let replace = Delta::simple_edit(Interval::new_closed_open(0, 1), Rope::from("!"), head.len());
+        assert!(engine.edit_rev(1, 1, 1, replace));
+        assert_eq!(2, engine.get_head_rev_id());
+    }
+
+    #[test]
+    fn undo_author() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev_by(1, 1, 1, 0, build_delta_1());
+        engine.edit_rev_by(2, 0, 2, 0, build_delta_2());
+        engine.edit_rev_by(1, 1, 3, 2, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("a"), 40));
+        assert_eq!("a0!3456789abcDEEFGIjklmnopqr888999stuvHIz", String::from(engine.get_head()));
+        let d = engine.undo_author(1);
+        assert_eq!("0!3456789abcdefGIjklmnopqr888stuvwHIyz", String::from(engine.get_head()));
+        assert_eq!("0!3456789abcdefGIjklmnopqr888stuvwHIyz",
+            d.apply_to_string("a0!3456789abcDEEFGIjklmnopqr888999stuvHIz"));
+    }
+
+    #[test]
+    fn last_edit_inverse() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert!(engine.last_edit_inverse().is_none());
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        let before = String::from(engine.get_head());
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        let head = String::from(engine.get_head());
+        let inverse = engine.last_edit_inverse().unwrap();
+        assert_eq!(before, inverse.apply_to_string(&head));
+    }
+
+    #[test]
+    fn gc_drops_superseded_undos() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        engine.undo([1].iter().cloned().collect());
+        engine.undo([0, 1].iter().cloned().collect());
+        engine.undo([1].iter().cloned().collect());
+        let head = String::from(engine.get_head());
+        engine.gc(&[1].iter().cloned().collect());
+        assert_eq!(head, String::from(engine.get_head()));
+        for pair in engine.revs.windows(2) {
+            let both_undo = match (&pair[0].edit, &pair[1].edit) {
+                (&Undo { .. }, &Undo { .. }) => true,
+                _ => false,
+            };
+            assert!(!both_undo);
+        }
+        assert_eq!(5, engine.get_head_rev_id());
+    }
+
+    #[test]
+    fn flush_gc() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        let head = String::from(engine.get_head());
+        let plugin = engine.register_plugin();
+        let other = engine.register_plugin();
+        assert_eq!(GcStatus::Deferred, engine.flush_gc(&[0].iter().cloned().collect()));
+        assert!(engine.get_rev(first).is_some());
+        engine.unregister_plugin(plugin);
+        assert_eq!(GcStatus::Deferred, engine.flush_gc(&BTreeSet::new()));
+        assert!(engine.get_rev(first).is_some());
+        engine.unregister_plugin(other);
+        assert_eq!(GcStatus::Collected, engine.flush_gc(&BTreeSet::new()));
+        assert!(engine.get_rev(first).is_none());
+        assert_eq!(head, String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn delete_conflicts() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.set_delete_policy(DeletePolicy::Detect);
+        let d1 = Delta::simple_edit(Interval::new_closed_open(0, 2), Rope::from("!"), TEST_STR.len());
+        engine.edit_rev(1, 1, 0, d1);
+        let d2 = Delta::simple_edit(Interval::new_closed_open(10, 15), Rope::from(""), TEST_STR.len());
+        engine.edit_rev(1, 2, 0, d2);
+        assert!(engine.delete_conflicts().is_empty());
+        let d3 = Delta::simple_edit(Interval::new_closed_open(12, 20), Rope::from("X"), TEST_STR.len());
+        engine.edit_rev(0, 3, 0, d3);
+        assert_eq!("!23456789XKLMNOPQRSTUVWXYZ", &String::from(engine.get_head())[..26]);
+        assert_eq!(vec![Interval::new_closed_open(10, 10)], engine.delete_conflicts());
+
+        // The conflict follows later inserts, history rewrites and gc.
+        let d4 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("?"), TEST_STR.len());
+        engine.edit_rev(2, 4, 0, d4);
+        assert_eq!(vec![Interval::new_closed_open(11, 11)], engine.delete_conflicts());
+        assert_eq!(Ok(()), engine.reprioritize(1, 3));
+        assert_eq!(vec![Interval::new_closed_open(11, 11)], engine.delete_conflicts());
+        engine.gc(&[1].iter().cloned().collect());
+        assert_eq!(vec![Interval::new_closed_open(11, 11)], engine.delete_conflicts());
+        engine.undo([2, 3].iter().cloned().collect());
+        let conflicts = engine.delete_conflicts();
+        assert_eq!(1, conflicts.len());
+        assert_eq!("CDE", engine.get_head().slice_to_string(conflicts[0].start(), conflicts[0].end()));
+        engine.gc(&[3].iter().cloned().collect());
+        assert!(engine.delete_conflicts().is_empty());
+    }
+
+    #[test]
+    fn delete_conflicts_after_gc_of_earlier_edit() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.set_delete_policy(DeletePolicy::Detect);
+        let d1 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("INS"), TEST_STR.len());
+        engine.edit_rev(1, 1, 0, d1);
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0, 5), Rope::from(""), TEST_STR.len() + 3);
+        engine.edit_rev(1, 2, 1, d2);
+        let base_rev = engine.get_head_rev_id();
+        let base_len = engine.get_head().len();
+        let d3 = Delta::simple_edit(Interval::new_closed_open(20, 25), Rope::from(""), base_len);
+        engine.edit_rev(1, 3, base_rev, d3);
+        let d4 = Delta::simple_edit(Interval::new_closed_open(20, 25), Rope::from(""), base_len);
+        engine.edit_rev(0, 4, base_rev, d4);
+        assert_eq!(vec![Interval::new_closed_open(20, 20)], engine.delete_conflicts());
+        // Collecting the earlier deletion, which covers text inserted by a
+        // revision that is kept, must not shift the conflict.
+        engine.gc(&[2].iter().cloned().collect());
+        assert_eq!(vec![Interval::new_closed_open(20, 20)], engine.delete_conflicts());
+        engine.undo([3, 4].iter().cloned().collect());
+        let conflicts = engine.delete_conflicts();
+        assert_eq!(1, conflicts.len());
+        assert_eq!("MNOPQ", engine.get_head().slice_to_string(conflicts[0].start(), conflicts[0].end()));
+    }
+
+    #[test]
+    fn delete_conflicts_union_policy() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new_closed_open(10, 15), Rope::from(""), TEST_STR.len());
+        engine.edit_rev(1, 1, 0, d1);
+        let d2 = Delta::simple_edit(Interval::new_closed_open(10, 15), Rope::from(""), TEST_STR.len());
+        engine.edit_rev(0, 2, 0, d2);
+        assert!(engine.delete_conflicts().is_empty());
+    }
+
+    #[test]
+    fn history_cursor() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        engine.edit_rev(0, 2, 0, build_delta_2());
+        let d3 = Delta::simple_edit(Interval::new_closed_open(0, 5), Rope::from("X"), engine.get_head().len());
+        let head_rev_id = engine.get_head_rev_id();
+        engine.edit_rev(1, 3, head_rev_id, d3);
+        let rev_ids: Vec<usize> = engine.revs.iter().map(|rev| rev.rev_id).collect();
+
+        let mut cursor = engine.history_cursor();
+        assert_eq!(rev_ids[0], cursor.rev_id());
+        assert!(cursor.prev().is_none());
+        let mut prev_text = String::from(TEST_STR);
+        for &rev_id in &rev_ids[1..] {
+            let (id, text, delta) = cursor.next().unwrap();
+            assert_eq!(rev_id, id);
+            assert_eq!(String::from(engine.get_rev(rev_id).unwrap()), String::from(&text));
+            assert_eq!(String::from(&text), delta.apply_to_string(&prev_text));
+            prev_text = String::from(text);
+        }
+        assert!(cursor.next().is_none());
+        for &rev_id in rev_ids[..3].iter().rev() {
+            let (id, text, delta) = cursor.prev().unwrap();
+            assert_eq!(rev_id, id);
+            assert_eq!(String::from(engine.get_rev(rev_id).unwrap()), String::from(&text));
+            assert_eq!(String::from(&text), delta.apply_to_string(&prev_text));
+            prev_text = String::from(text);
+        }
+        assert!(cursor.prev().is_none());
+    }
+
+    #[test]
+    fn fallible_with_gced_base() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        engine.gc(&[0].iter().cloned().collect());
+        let head = String::from(engine.get_head());
+        let head_rev_id = engine.get_head_rev_id();
+        let len = engine.get_head().len();
+        let mk_delta = || Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("a"), len);
+
+        assert!(engine.get_rev(first).is_none());
+        assert!(engine.rev_meta(first).is_none());
+        assert!(engine.rev_union_len(first).is_none());
+        assert!(engine.deltas_since(first).is_none());
+        assert!(!engine.is_equivalent_revision(first, first));
+        assert_eq!(Some(EngineError::MissingRevision(first)), engine.try_delta_rev_head(first).err());
+        assert_eq!(Err(EngineError::MissingRevision(first)), engine.reprioritize(first, 5));
+        assert_eq!(Err(EngineError::MissingRevision(first)), engine.try_edit_rev(1, 2, first, mk_delta()));
+        assert_eq!(Err(EngineError::MissingRevision(first)),
+            engine.try_edit_rev_with_meta(1, 2, first, mk_delta(), "meta".to_owned()));
+        assert_eq!(Err(EngineError::MissingRevision(first)), engine.try_edit_rev_by(7, 1, 2, first, mk_delta()));
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+
+        assert_eq!(Ok(true), engine.try_edit_rev(1, 2, head_rev_id, mk_delta()));
+        assert_eq!(format!("a{}", head), String::from(engine.get_head()));
+        let delta = engine.try_delta_rev_head(head_rev_id).unwrap();
+        assert_eq!(String::from(engine.get_head()), delta.apply_to_string(&head));
+    }
+
+    #[test]
+    fn verify_rev() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        engine.edit_rev(0, 2, 0, build_delta_2());
+        engine.undo([1].iter().cloned().collect());
+        let rev_ids: Vec<usize> = engine.revs.iter().map(|rev| rev.rev_id).collect();
+        for &rev_id in &rev_ids {
+            assert_eq!(Some(true), engine.verify_rev(rev_id));
+        }
+        assert_eq!(None, engine.verify_rev(100));
+        assert_eq!(Ok(()), engine.reprioritize(1, 3));
+        for rev in &engine.revs {
+            assert_eq!(Some(true), engine.verify_rev(rev.rev_id));
+        }
+        engine.gc(&[1].iter().cloned().collect());
+        assert_eq!(Some(true), engine.verify_rev(engine.get_head_rev_id()));
+        let head_rev_id = engine.get_head_rev_id();
+        let len = engine.get_head().len();
+        engine.edit_rev(1, 3, head_rev_id, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("!"), len));
+        assert_eq!(content_checksum(&engine.get_head()), engine.revs.last().unwrap().checksum);
+
+        // Corrupt a stored subset, as a rebasing bug might, in a revision
+        // that hasn't been verified before.
+        let ix = engine.revs.len() - 1;
+        let mut sb = SubsetBuilder::new();
+        sb.add_range(0, 1);
+        let corrupt = engine.revs[ix].deletes_from_union.union(&sb.build());
+        engine.revs[ix].deletes_from_union = corrupt;
+        assert_eq!(Some(false), engine.verify_rev(engine.get_head_rev_id()));
+    }
+
+    #[test]
+    fn set_head_to() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let head = String::from(engine.get_head());
+        let target = "0123456789abcDEEF ghijklmn opqr999 stuvz";
+        assert_eq!(Ok(true), engine.set_head_to(1, 2, Rope::from(target)));
+        assert_eq!(target, String::from(engine.get_head()));
+        assert_eq!(Ok(false), engine.set_head_to(1, 3, Rope::from(target)));
+        engine.undo([2].iter().cloned().collect());
+        assert_eq!(head, String::from(engine.get_head()));
+
+        engine.set_edit_filter(Box::new(|_| false));
+        assert_eq!(Err(EngineError::Rejected), engine.set_head_to(1, 4, Rope::from(target)));
+        assert_eq!(head, String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn compact_union() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new_closed_open(10, 50), Rope::from("x"), TEST_STR.len());
+        engine.edit_rev(1, 1, 0, d1);
+        let len = engine.get_head().len();
+        let head_rev_id = engine.get_head_rev_id();
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0, 5), Rope::from(""), len);
+        engine.edit_rev(1, 2, head_rev_id, d2);
+        engine.undo([1].iter().cloned().collect());
+        engine.undo(BTreeSet::new());
+        let head = String::from(engine.get_head());
+        assert_eq!(TEST_STR.len() + 1, engine.union_len());
+
+        engine.compact_union();
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(head.len(), engine.union_len());
+        assert_eq!(Some(head.len()), engine.rev_union_len(engine.get_head_rev_id()));
+        assert_eq!(Some(true), engine.verify_rev(engine.get_head_rev_id()));
+        let head_rev_id = engine.get_head_rev_id();
+        engine.edit_rev(1, 3, head_rev_id, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("!"), head.len()));
+        assert_eq!(format!("!{}", head), String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn rev_base() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        engine.edit_rev(0, 2, 0, build_delta_2());
+        let second = engine.get_head_rev_id();
+        let d3 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("!"), engine.get_head().len());
+        engine.edit_rev(1, 3, second, d3);
+        let third = engine.get_head_rev_id();
+        engine.undo([3].iter().cloned().collect());
+        assert_eq!(Some(0), engine.rev_base(first));
+        assert_eq!(Some(0), engine.rev_base(second));
+        assert_eq!(Some(second), engine.rev_base(third));
+        assert_eq!(None, engine.rev_base(engine.get_head_rev_id()));
+        assert_eq!(None, engine.rev_base(100));
+        engine.gc(&[1].iter().cloned().collect());
+        assert_eq!(Some(0), engine.rev_base(second));
+        assert_eq!(Some(second), engine.rev_base(third));
+    }
+
+    #[test]
+    fn head_author_count() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert_eq!(0, engine.head_author_count());
+        engine.edit_rev_by(10, 1, 1, 0, build_delta_1());
+        engine.edit_rev_by(20, 0, 2, 0, build_delta_2());
+        let head_rev_id = engine.get_head_rev_id();
+        let d3 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("!"), engine.get_head().len());
+        engine.edit_rev_by(30, 1, 3, head_rev_id, d3);
+        let head_rev_id = engine.get_head_rev_id();
+        let d4 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("?"), engine.get_head().len());
+        engine.edit_rev_by(10, 1, 4, head_rev_id, d4);
+        assert_eq!(3, engine.head_author_count());
+        engine.undo([2].iter().cloned().collect());
+        assert_eq!(2, engine.head_author_count());
+        engine.undo([1, 4].iter().cloned().collect());
+        assert_eq!(2, engine.head_author_count());
+        engine.undo([1, 2, 4].iter().cloned().collect());
+        assert_eq!(1, engine.head_author_count());
+    }
+
+    #[test]
+    fn apply_remote() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert_eq!(Ok(true), engine.apply_remote(1, 1, 0, build_delta_1()));
+        let head = String::from(engine.get_head());
+        let head_rev_id = engine.get_head_rev_id();
+
+        let mut bad = Rope::from("bad\ninsert");
+        bad.set_len_for_test(3);
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), bad, TEST_STR.len());
+        assert_eq!(Err(EngineError::MalformedInsert), engine.apply_remote(0, 2, 0, d));
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+
+        assert_eq!(Err(EngineError::MissingRevision(100)), engine.apply_remote(0, 2, 100, build_delta_2()));
+        assert_eq!(Ok(true), engine.apply_remote(0, 2, 0, build_delta_2()));
+    }
+
+    #[test]
+    fn revert_to() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        let first_text = String::from(engine.get_head());
+        let d2 = Delta::simple_edit(Interval::new_closed_open(3, 10), Rope::from("xyz"), first_text.len());
+        engine.edit_rev(1, 2, first, d2);
+        let head = String::from(engine.get_head());
+
+        let delta = engine.revert_to(1, 3, first).unwrap();
+        assert_eq!(first_text, delta.apply_to_string(&head));
+        assert_eq!(first_text, String::from(engine.get_head()));
+        assert_eq!(first_text, String::from(engine.get_rev(first).unwrap()));
+        engine.undo([3].iter().cloned().collect());
+        assert_eq!(head, String::from(engine.get_head()));
+
+        assert_eq!(Err(EngineError::MissingRevision(100)), engine.revert_to(1, 4, 100).map(|_| ()));
+
+        engine.set_edit_filter(Box::new(|_| false));
+        let head_rev_id = engine.get_head_rev_id();
+        assert_eq!(Err(EngineError::Rejected), engine.revert_to(1, 4, first).map(|_| ()));
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+    }
+
+    #[test]
+    fn can_undo_redo() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert!(!engine.can_undo());
+        assert!(!engine.can_redo());
+        let d1 = Delta::simple_edit(Interval::new_closed_open(0,0), Rope::from("h"), TEST_STR.len());
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 1, first_rev, d1);
+        assert!(engine.can_undo());
+        assert!(!engine.can_redo());
+        engine.undo_last(1);
+        assert!(!engine.can_undo());
+        assert!(engine.can_redo());
+        engine.undo([].iter().cloned().collect());
+        assert!(engine.can_undo());
+        assert!(!engine.can_redo());
+    }
+
+    #[derive(Clone)]
+    struct BytesInfo(usize);
+
+    impl Leaf for Vec<u8> {
+        fn len(&self) -> usize {
+            self.len()
+        }
+
+        fn is_ok_child(&self) -> bool {
+            !self.is_empty()
+        }
+
+        fn push_maybe_split(&mut self, other: &Vec<u8>, iv: Interval) -> Option<Vec<u8>> {
+            let (start, end) = iv.start_end();
+            self.extend_from_slice(&other[start..end]);
+            None
+        }
+    }
+
+    impl NodeInfo for BytesInfo {
+        type L = Vec<u8>;
+
+        fn accumulate(&mut self, other: &Self) {
+            self.0 += other.0;
+        }
+
+        fn compute_info(l: &Vec<u8>) -> BytesInfo {
+            BytesInfo(l.len())
+        }
+    }
+
+    fn bytes(node: &Node<BytesInfo>) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut cursor = Cursor::new(node, 0);
+        while let Some((leaf, _)) = cursor.get_leaf() {
+            result.extend_from_slice(leaf);
+            if cursor.next_leaf().is_none() {
+                break;
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn custom_node_info() {
+        let mut engine = Engine::new(Node::<BytesInfo>::from_leaf(b"hello".to_vec()));
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 1),
+            Node::from_leaf(b"j".to_vec()), 5);
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 1, first_rev, d);
+        assert_eq!(b"jello".to_vec(), bytes(&engine.get_head()));
+        assert_eq!(Some(true), engine.verify_rev(engine.get_head_rev_id()));
+        engine.undo_last(1);
+        assert_eq!(b"hello".to_vec(), bytes(engine.head_ref()));
+    }
+
+    #[test]
+    fn transaction() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 5, first_rev, Delta::simple_edit(Interval::new_closed_open(0, 0),
+            Rope::from("a"), TEST_STR.len()));
+        let before = String::from(engine.get_head());
+        {
+            let mut txn = engine.begin_transaction();
+            assert_eq!(6, txn.undo_group());
+            let rev = txn.engine().get_head_rev_id();
+            let len = txn.engine().head_ref().len();
+            txn.edit_rev(1, rev, Delta::simple_edit(Interval::new_closed_open(0, 2), Rope::from(""), len));
+            let mut nested = txn.begin_transaction();
+            assert_eq!(6, nested.undo_group());
+            let rev = nested.engine().get_head_rev_id();
+            let len = nested.engine().head_ref().len();
+            nested.edit_rev(1, rev, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("z"), len));
+        }
+        assert_eq!(format!("z{}", &TEST_STR[1..]), String::from(engine.get_head()));
+        engine.undo_last(1);
+        assert_eq!(before, String::from(engine.get_head()));
+        assert_eq!(7, engine.begin_transaction().undo_group());
+    }
+
+    #[test]
+    fn head_write_to() {
+        let mut engine = Engine::new(Rope::from(""));
+        let mut out = Vec::new();
+        engine.head_write_to(&mut out).unwrap();
+        assert!(out.is_empty());
+        let text = TEST_STR.repeat(100);
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from(&text), 0);
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 1, first_rev, d);
+        engine.head_write_to(&mut out).unwrap();
+        assert_eq!(String::from(engine.get_head()).into_bytes(), out);
+    }
+
+    #[test]
+    fn catch_up() {
+        // The hash is part of the protocol, so its algorithm must not change.
+        assert_eq!(98 + 99 * 1_000_003, content_checksum(&Rope::from("ab")));
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let client_hash = Delta::doc_hash(&Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        let first_text = engine.get_head();
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        let head = String::from(engine.get_head());
+
+        match engine.catch_up(0, client_hash) {
+            CatchUp::Delta(d) => assert_eq!(head, d.apply_to_string(TEST_STR)),
+            CatchUp::FullResync(_) => panic!("expected a delta"),
+        }
+        match engine.catch_up(0, client_hash ^ 1) {
+            CatchUp::FullResync(text) => assert_eq!(head, String::from(text)),
+            CatchUp::Delta(_) => panic!("expected a full resync"),
+        }
+
+        let first_hash = build_delta_1().new_doc_hash(client_hash, &Rope::from(TEST_STR));
+        assert_eq!(content_checksum(&first_text), first_hash);
+        engine.gc(&[0].iter().cloned().collect());
+        match engine.catch_up(first, first_hash) {
+            CatchUp::FullResync(text) => assert_eq!(head, String::from(text)),
+            CatchUp::Delta(_) => panic!("expected a full resync"),
+        }
+    }
+
+    #[test]
+    fn no_history() {
+        let mut engine = Engine::new_no_history(Rope::from(""));
+        let mut expected = String::new();
+        for i in 0..100 {
+            let s = format!("{} ", i);
+            let len = expected.len();
+            let d = Delta::simple_edit(Interval::new_closed_open(len / 2, len / 2), Rope::from(&s), len);
+            expected = d.apply_to_string(&expected);
+            let head_rev_id = engine.get_head_rev_id();
+            engine.edit_rev(1, i, head_rev_id, d);
+            assert_eq!(1, engine.revs.len());
+        }
+        assert_eq!(expected, String::from(engine.get_head()));
+        assert_eq!(expected.len(), engine.union_len());
+        engine.undo_last(1);
+        assert_eq!(expected, String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn reset_contents() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        let old_head_rev_id = engine.get_head_rev_id();
+        engine.reset_contents(Rope::from("reloaded"));
+        assert_eq!("reloaded", String::from(engine.get_head()));
+        assert_eq!("reloaded".len(), engine.union_len());
+        assert!(engine.get_head_rev_id() > old_head_rev_id);
+        assert!(engine.get_rev(old_head_rev_id).is_none());
+        let head_rev_id = engine.get_head_rev_id();
+        engine.edit_rev(1, 2, head_rev_id, Delta::simple_edit(Interval::new_closed_open(0, 2),
+            Rope::from("p"), 8));
+        assert!(engine.get_head_rev_id() > head_rev_id);
+        assert_eq!("ploaded", String::from(engine.get_head()));
+        engine.undo_last(1);
+        assert_eq!("reloaded", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn tombstone_ratio() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert_eq!(0, engine.tombstone_len());
+        assert_eq!(0.0, engine.tombstone_ratio());
+        let n = TEST_STR.len();
+        engine.edit_rev(1, 1, 0, Delta::simple_edit(Interval::new_closed_open(2, n), Rope::from(""), n));
+        assert_eq!(n - 2, engine.tombstone_len());
+        assert_eq!((n - 2) as f64 / 2.0, engine.tombstone_ratio());
+        // gc always keeps the head revision, and with it its tombstones.
+        engine.gc(&[1].iter().cloned().collect());
+        assert_eq!(n - 2, engine.tombstone_len());
+        let head_rev_id = engine.get_head_rev_id();
+        engine.edit_rev(1, 2, head_rev_id, Delta::simple_edit(Interval::new_closed_open(2, 2), Rope::from("a"), 2));
+        engine.gc(&[1, 2].iter().cloned().collect());
+        assert_eq!(0, engine.tombstone_len());
+        assert_eq!(0.0, engine.tombstone_ratio());
+    }
+
+    #[test]
+    fn edit_filter() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.set_edit_filter(Box::new(|d: &Delta<RopeInfo>| d.all_inserted().len() <= 3));
+        let n = TEST_STR.len();
+        let small = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("abc"), n);
+        let large = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("abcd"), n);
+        assert_eq!(Err(EngineError::Rejected), engine.try_edit_rev(1, 1, 0, large.clone()));
+        assert_eq!(Err(EngineError::Rejected), engine.apply_remote(1, 1, 0, large.clone()));
+        assert!(!engine.edit_rev(1, 1, 0, large));
+        assert_eq!(0, engine.get_head_rev_id());
+        assert_eq!(TEST_STR, String::from(engine.get_head()));
+        assert!(engine.edit_rev(1, 1, 0, small));
+        assert_eq!(format!("abc{}", TEST_STR), String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn operation_log_replay() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 0, 0, build_delta_1());
+        engine.edit_rev(0, 1, 0, build_delta_2());
+        engine.undo([0].iter().cloned().collect());
+        let log = engine.operation_log(0).unwrap();
+        assert_eq!(3, log.len());
+        match log[2] {
+            EngineOp::Undo { ref groups, .. } => assert_eq!(&[0].iter().cloned().collect::<BTreeSet<_>>(), groups),
+            EngineOp::Edit { .. } => panic!("expected an undo"),
+        }
+
+        let mut peer = Engine::new(Rope::from(TEST_STR));
+        peer.replay(log).unwrap();
+        assert_eq!(String::from(engine.get_head()), String::from(peer.get_head()));
+        peer.undo(BTreeSet::new());
+        engine.undo(BTreeSet::new());
+        assert_eq!(String::from(engine.get_head()), String::from(peer.get_head()));
+
+        let head_rev_id = engine.get_head_rev_id();
+        assert!(engine.operation_log(head_rev_id).unwrap().is_empty());
+        assert_eq!(Err(EngineError::MissingRevision(100)), engine.operation_log(100).map(|log| log.len()));
+    }
+
+    #[test]
+    fn replay_maps_rev_ids() {
+        let n = TEST_STR.len();
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("a"), n));
+        let first = engine.get_head_rev_id();
+        engine.edit_rev(1, 2, first, Delta::simple_edit(Interval::new_closed_open(1, 1), Rope::from("b"), n + 1));
+        let log = engine.operation_log(0).unwrap();
+
+        // An undo that changes nothing still takes a revision id on the peer.
+        let mut peer = Engine::new(Rope::from(TEST_STR));
+        peer.undo(BTreeSet::new());
+        peer.replay(log).unwrap();
+        assert_eq!(format!("ab{}", TEST_STR), String::from(peer.get_head()));
+        assert_eq!(String::from(engine.get_head()), String::from(peer.get_head()));
+    }
+
+    #[test]
+    fn set_head_from_content() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let head = String::from(engine.get_head());
+        let merged = format!("{}{}", &head[..10], &TEST_STR[20..]);
+        let delta = engine.set_head_from_content(1, 2, Rope::from(merged.as_str())).unwrap();
+        assert_eq!(merged, delta.apply_to_string(&head));
+        assert_eq!(merged, String::from(engine.get_head()));
+        engine.undo([2].iter().cloned().collect());
+        assert_eq!(head, String::from(engine.get_head()));
+        let head_rev_id = engine.get_head_rev_id();
+        assert!(engine.set_head_from_content(1, 3, Rope::from(head.as_str())).unwrap().is_identity());
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+
+        engine.set_max_len(head.len());
+        let longer = format!("{}!", head);
+        assert_eq!(Err(EngineError::TooLong),
+            engine.set_head_from_content(1, 4, Rope::from(longer.as_str())).map(|_| ()));
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+    }
+
+    #[test]
+    fn edit_rev_incremental() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let old_head = String::from(engine.get_head());
+        // Concurrent with the first edit.
+        let IncrementalUpdate { new_rev_id, delta, new_head_len } =
+            engine.edit_rev_incremental(0, 2, 0, build_delta_2());
+        let head = String::from(engine.get_head());
+        assert_eq!(engine.get_head_rev_id(), new_rev_id);
+        assert_eq!(head.len(), new_head_len);
+        assert_eq!(head, delta.apply_to_string(&old_head));
+
+        let identity = Builder::new(head.len()).build();
+        let IncrementalUpdate { new_rev_id, delta, .. } = engine.edit_rev_incremental(0, 3, new_rev_id, identity);
+        assert!(delta.is_identity());
+        assert_eq!(engine.get_head_rev_id(), new_rev_id);
+        assert_eq!(head, String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn deletes_from_union_cache() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let first_rev = engine.get_head_rev_id();
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("x"), engine.get_head().len());
+        engine.edit_rev(1, 2, first_rev, d2);
+        UNION_FOLDS.with(|folds| folds.set(0));
+        let expected = String::from(engine.get_rev(first_rev).unwrap());
+        for _ in 0..10 {
+            assert_eq!(expected, String::from(engine.get_rev(first_rev).unwrap()));
+        }
+        assert_eq!(1, UNION_FOLDS.with(|folds| folds.get()));
+        // A hit shares the cached subset rather than copying it.
+        let first_ix = engine.find_rev(first_rev).unwrap();
+        match (engine.deletes_from_union_for_index(first_ix), engine.deletes_from_union_for_index(first_ix)) {
+            (UnionDeletes::Cached(a), UnionDeletes::Cached(b)) => assert!(Arc::ptr_eq(&a, &b)),
+            _ => panic!("expected cached deletes"),
+        }
+
+        let h2 = String::from(engine.get_head());
+        let d3 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("y"), engine.get_head().len());
+        let head_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 3, head_rev, d3);
+        assert_eq!(expected, String::from(engine.get_rev(first_rev).unwrap()));
+        assert_eq!(2, UNION_FOLDS.with(|folds| folds.get()));
+
+        engine.undo([3].iter().cloned().collect());
+        assert_eq!(expected, String::from(engine.get_rev(first_rev).unwrap()));
+        engine.gc(&[3].iter().cloned().collect());
+        assert_eq!(expected, String::from(engine.get_rev(first_rev).unwrap()));
+        assert_eq!(h2, String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn preview_undo() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let head_rev = engine.get_head_rev_id();
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("x"), engine.get_head().len());
+        engine.edit_rev(1, 2, head_rev, d2);
+        let head = engine.get_head();
+        let head_rev = engine.get_head_rev_id();
+        let groups: BTreeSet<usize> = [1].iter().cloned().collect();
+        let preview = engine.preview_undo(&groups);
+        assert_eq!(head_rev, engine.get_head_rev_id());
+        assert_eq!(String::from(&head), String::from(engine.get_head()));
+
+        engine.undo(groups);
+        assert_eq!(String::from(engine.get_head()), String::from(preview.apply(&head)));
+        assert_eq!(format!("x{}", TEST_STR), String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn version_is_monotonic() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let mut versions = vec![engine.version()];
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        versions.push(engine.version());
+        let head_rev = engine.get_head_rev_id();
+        let d2 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("x"), engine.get_head().len());
+        engine.edit_rev(1, 2, head_rev, d2);
+        versions.push(engine.version());
+        engine.undo([2].iter().cloned().collect());
+        versions.push(engine.version());
+        engine.undo(BTreeSet::new());
+        versions.push(engine.version());
+        engine.undo([1].iter().cloned().collect());
+        versions.push(engine.version());
+        engine.gc(&[1, 2].iter().cloned().collect());
+        versions.push(engine.version());
+        let head_rev = engine.get_head_rev_id();
+        let d3 = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("y"), engine.get_head().len());
+        engine.edit_rev(1, 3, head_rev, d3);
+        versions.push(engine.version());
+        engine.compact_union();
+        versions.push(engine.version());
+        assert!(versions.windows(2).all(|w| w[0] <= w[1]), "versions decreased: {:?}", versions);
+        assert!(versions[0] < versions[versions.len() - 1]);
+    }
+
+    #[test]
+    fn version_changes_on_reprioritize() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from("a"), TEST_STR.len());
+        let d2 = Delta::simple_edit(Interval::new_closed_open(5, 5), Rope::from("b"), TEST_STR.len());
+        engine.edit_rev(1, 1, 0, d1);
+        engine.edit_rev(2, 2, 0, d2);
+        let head_rev_id = engine.get_head_rev_id();
+        let head = String::from(engine.get_head());
+        let version = engine.version();
+        assert_eq!(Ok(()), engine.reprioritize(1, 3));
+        assert_eq!(head_rev_id, engine.get_head_rev_id());
+        assert!(head != String::from(engine.get_head()));
+        assert!(engine.version() > version);
+    }
+
+    #[test]
+    fn author_activity() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new_closed_open(10, 10), Rope::from("abc"), TEST_STR.len());
+        engine.edit_rev_by(1, 1, 1, 0, d1);
+        let rev = engine.get_head_rev_id();
+        let d2 = Delta::simple_edit(Interval::new_closed_open(40, 45), Rope::from(""), TEST_STR.len() + 3);
+        engine.edit_rev_by(2, 1, 2, rev, d2);
+        let rev = engine.get_head_rev_id();
+        let d3 = Delta::simple_edit(Interval::new_closed_open(20, 20), Rope::from("de"), TEST_STR.len() - 2);
+        engine.edit_rev_by(1, 1, 3, rev, d3);
+        let activity: Vec<_> = engine.author_activity(0).unwrap().into_iter()
+            .map(|(author, iv)| (author, iv.start_end()))
+            .collect();
+        assert_eq!(vec![(1, (10, 22)), (2, (42, 42))], activity);
+
+        let activity: Vec<_> = engine.author_activity(rev).unwrap().into_iter()
+            .map(|(author, iv)| (author, iv.start_end()))
+            .collect();
+        assert_eq!(vec![(1, (20, 22))], activity);
+        assert_eq!(Some(EngineError::MissingRevision(99)), engine.author_activity(99).err());
+    }
+
+    #[test]
+    fn is_pristine() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert!(engine.is_pristine());
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        assert!(!engine.is_pristine());
+        engine.undo([1].iter().cloned().collect());
+        assert!(engine.is_pristine());
+        engine.undo(BTreeSet::new());
+        assert!(!engine.is_pristine());
+
+        // Typing and then deleting text leaves the content unchanged.
+        let head_rev = engine.get_head_rev_id();
+        let len = engine.get_head().len();
+        engine.edit_rev(1, 2, head_rev, Delta::simple_edit(Interval::new_closed_open(0, len), Rope::from(TEST_STR), len));
+        assert!(engine.is_pristine());
+        engine.gc(&[1, 2].iter().cloned().collect());
+        assert!(engine.is_pristine());
+
+        engine.reset_contents(Rope::from("new"));
+        assert!(engine.is_pristine());
+
+        // Content whose checksum collides with the initial contents isn't
+        // mistaken for them.
+        let mut engine = Engine::new(Rope::from("draiajmjpg"));
+        assert_eq!(Ok(true), engine.set_head_to(1, 1, Rope::from("srvivypqwj")));
+        assert_eq!(content_checksum(&Rope::from("draiajmjpg")), engine.revs.last().unwrap().checksum);
+        assert!(!engine.is_pristine());
+    }
+
+    #[test]
+    fn squash() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        let len = engine.get_head().len();
+        engine.edit_rev(1, 2, first, Delta::simple_edit(Interval::new_closed_open(0, 3), Rope::from("x"), len));
+        let second = engine.get_head_rev_id();
+        let len = engine.get_head().len();
+        engine.edit_rev(1, 3, second, Delta::simple_edit(Interval::new_closed_open(len, len), Rope::from("!"), len));
+        let third = engine.get_head_rev_id();
+        let head = String::from(engine.get_head());
+
+        assert_eq!(Err(EngineError::MissingRevision(42)), engine.squash(first, 42, 4));
+        assert_eq!(Err(EngineError::CannotSquash(first)), engine.squash(second, first, 4));
+        assert_eq!(Err(EngineError::NotAnEdit(0)), engine.squash(0, first, 4));
+
+        assert_eq!(Ok(()), engine.squash(first, second, 4));
+        assert_eq!(head, String::from(engine.get_head()));
+        assert_eq!(None, engine.find_rev(first));
+        assert!(engine.find_rev(second).is_some());
+        engine.undo([4].iter().cloned().collect());
+        assert_eq!(format!("{}!", TEST_STR), String::from(engine.get_head()));
+        engine.undo(BTreeSet::new());
+        assert_eq!(head, String::from(engine.get_head()));
+
+        engine.undo([3].iter().cloned().collect());
+        let head_rev = engine.get_head_rev_id();
+        // The third edit is now undone.
+        assert_eq!(Err(EngineError::CannotSquash(third)), engine.squash(second, head_rev, 5));
+    }
+
+    #[test]
+    fn rev_inserted_text() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        let len = engine.get_head().len();
+        engine.edit_rev(1, 2, first, Delta::simple_edit(Interval::new_closed_open(0, 5), Rope::from("hello"), len));
+        let second = engine.get_head_rev_id();
+        engine.undo([2].iter().cloned().collect());
+        let undo_rev = engine.get_head_rev_id();
+
+        assert_eq!(Some("DEEF999".to_owned()), engine.rev_inserted_text(first).map(String::from));
+        assert_eq!(Some("hello".to_owned()), engine.rev_inserted_text(second).map(String::from));
+        assert!(engine.rev_inserted_text(undo_rev).is_none());
+        assert!(engine.rev_inserted_text(0).is_none());
+        assert!(engine.rev_inserted_text(42).is_none());
+    }
+
+    #[test]
+    fn max_len() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let n = TEST_STR.len();
+        engine.set_max_len(n + 3);
+        let large = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("abcd"), n);
+        assert_eq!(Err(EngineError::TooLong), engine.try_edit_rev(1, 1, 0, large.clone()));
+        assert_eq!(Err(EngineError::TooLong), engine.apply_remote(1, 1, 0, large.clone()));
+        assert!(!engine.edit_rev(1, 1, 0, large));
+        assert_eq!(0, engine.get_head_rev_id());
+        assert_eq!(TEST_STR, String::from(engine.get_head()));
+
+        let small = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("abc"), n);
+        assert!(engine.edit_rev(1, 1, 0, small));
+        assert_eq!(n + 3, engine.get_head().len());
+
+        engine.set_max_len(10);
+        let head_rev = engine.get_head_rev_id();
+        let replace = Delta::simple_edit(Interval::new_closed_open(0, 1), Rope::from("xy"), n + 3);
+        assert_eq!(Err(EngineError::TooLong), engine.try_edit_rev(1, 2, head_rev, replace));
+        let delete = Delta::simple_edit(Interval::new_closed_open(0, 3), Rope::from(""), n + 3);
+        assert_eq!(Ok(true), engine.try_edit_rev(1, 2, head_rev, delete));
+        assert_eq!(TEST_STR, String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn check_invariants() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert_eq!(Ok(()), engine.check_invariants());
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        engine.edit_rev(0, 2, 0, build_delta_2());
+        engine.undo([1].iter().cloned().collect());
+        assert_eq!(Ok(()), engine.check_invariants());
+        engine.gc(&[1].iter().cloned().collect());
+        assert_eq!(Ok(()), engine.check_invariants());
+
+        let head_ix = engine.revs.len() - 1;
+        let head_rev_id = engine.get_head_rev_id();
+        engine.revs[head_ix].union_str_len = 10;
+        let errors = engine.check_invariants().unwrap_err();
+        assert!(errors.iter().any(|e| e.starts_with(&format!("revision {}: deletes_from_union extends", head_rev_id))),
+            "{:?}", errors);
+        assert!(errors.iter().any(|e| e.starts_with("head union_str_len")), "{:?}", errors);
+    }
+
+    #[test]
+    fn engine_is_send() {
+        // Editors holding an engine are moved into plugin threads.
+        fn assert_send<T: Send>() {}
+        assert_send::<Engine<RopeInfo>>();
+    }
+
+    #[test]
+    fn squash_rejects_later_undo_of_squashed_group() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let n = TEST_STR.len();
+        engine.edit_rev_with_meta(1, 1, 0, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("a"), n),
+            "type".to_owned());
+        let r1 = engine.get_head_rev_id();
+        engine.edit_rev_with_meta(1, 2, r1, Delta::simple_edit(Interval::new_closed_open(1, 1), Rope::from("b"), n + 1),
+            "type".to_owned());
+        let r2 = engine.get_head_rev_id();
+        engine.undo([2].iter().cloned().collect());
+        let undo_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 3, undo_rev, Delta::simple_edit(Interval::new_closed_open(n + 1, n + 1), Rope::from("!"), n + 1));
+        engine.undo(BTreeSet::new());
+        let head = String::from(engine.get_head());
+
+        assert_eq!(Err(EngineError::CannotSquash(undo_rev)), engine.squash(r1, r2, 10));
+        assert_eq!(head, String::from(engine.get_head()));
+
+        // Without the later undos, the squash goes through and keeps the
+        // shared meta.
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        engine.edit_rev_with_meta(1, 1, 0, Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("a"), n),
+            "type".to_owned());
+        engine.edit_rev_with_meta(1, 2, r1, Delta::simple_edit(Interval::new_closed_open(1, 1), Rope::from("b"), n + 1),
+            "type".to_owned());
+        assert_eq!(Ok(()), engine.squash(r1, r2, 10));
+        assert_eq!(Some("type"), engine.rev_meta(r2));
+    }
+
+    #[test]
+    fn head_ref() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        assert_eq!(TEST_STR, String::from(engine.head_ref()));
+        engine.edit_rev(1, 1, 0, build_delta_1());
+        let first = engine.get_head_rev_id();
+        engine.edit_rev(0, 2, 0, build_delta_2());
+        let expected = "0!3456789abcDEEFGIjklmnopqr888999stuvHIz";
+        let text: String = engine.head_ref().iter_chunks(0, expected.len()).collect();
+        assert_eq!(expected, text);
+        engine.reprioritize(first, 0).unwrap();
+        assert_eq!(String::from(engine.get_rev(engine.get_head_rev_id()).unwrap()),
+            String::from(engine.head_ref()));
+        engine.undo([1].iter().cloned().collect());
+        assert_eq!("0!3456789abcdefGIjklmnopqr888stuvwHIyz", String::from(engine.head_ref()));
+        engine.gc(&[1].iter().cloned().collect());
+        assert_eq!("0!3456789abcdefGIjklmnopqr888stuvwHIyz", String::from(engine.head_ref()));
+    }
 }
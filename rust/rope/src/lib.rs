@@ -16,6 +16,7 @@
 
 extern crate bytecount;
 extern crate memchr;
+extern crate unicode_segmentation;
 
 pub mod tree;
 pub mod breaks;
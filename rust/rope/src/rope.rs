@@ -26,6 +26,7 @@ use interval::Interval;
 
 use bytecount;
 use memchr::memchr;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
 
 const MIN_LEAF: usize = 511;
 const MAX_LEAF: usize = 1024;
@@ -60,6 +61,24 @@ impl Leaf for String {
     }
 }
 
+/// A line ending convention.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    /// Unix style, `\n`.
+    Lf,
+    /// MS-DOS style, `\r\n`.
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct RopeInfo {
     lines: usize,
@@ -260,15 +279,53 @@ impl Rope {
         cursor.next::<BaseMetric>()
     }
 
-    // graphemes should probably be developed as a cursor-based interface
+    /// Return the offset of the extended grapheme cluster boundary (UAX #29)
+    /// before `offset`, or `None` if `offset` is 0.
     pub fn prev_grapheme_offset(&self, offset: usize) -> Option<usize> {
-        // TODO: actual grapheme analysis
-        self.prev_codepoint_offset(offset)
+        let mut cursor = Cursor::new(self, offset);
+        let mut graphemes = GraphemeCursor::new(offset, self.len(), true);
+        loop {
+            let (leaf, offset_in_leaf) = cursor.get_leaf()?;
+            match graphemes.prev_boundary(leaf, cursor.pos() - offset_in_leaf) {
+                Ok(prev) => return prev,
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    cursor.prev_leaf()?;
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_grapheme_context(&mut graphemes, end),
+                Err(e) => panic!("unexpected grapheme cursor state {:?}", e),
+            }
+        }
     }
 
+    /// Return the offset of the extended grapheme cluster boundary (UAX #29)
+    /// after `offset`, or `None` if `offset` is the end of the rope.
     pub fn next_grapheme_offset(&self, offset: usize) -> Option<usize> {
-        // TODO: actual grapheme analysis
-        self.next_codepoint_offset(offset)
+        let mut cursor = Cursor::new(self, offset);
+        let mut graphemes = GraphemeCursor::new(offset, self.len(), true);
+        loop {
+            let (leaf, offset_in_leaf) = cursor.get_leaf()?;
+            match graphemes.next_boundary(leaf, cursor.pos() - offset_in_leaf) {
+                Ok(next) => return next,
+                Err(GraphemeIncomplete::NextChunk) => {
+                    cursor.next_leaf()?;
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_grapheme_context(&mut graphemes, end),
+                Err(e) => panic!("unexpected grapheme cursor state {:?}", e),
+            }
+        }
+    }
+
+    // Give `graphemes` the leaf text that ends at `end`, which it asked for
+    // as context.
+    fn provide_grapheme_context(&self, graphemes: &mut GraphemeCursor, end: usize) {
+        let mut cursor = Cursor::new(self, end);
+        let (leaf, offset_in_leaf) = cursor.get_leaf().unwrap();
+        if offset_in_leaf > 0 {
+            graphemes.provide_context(&leaf[..offset_in_leaf], end - offset_in_leaf);
+        } else {
+            let (prev_leaf, _) = cursor.prev_leaf().unwrap();
+            graphemes.provide_context(prev_leaf, end - prev_leaf.len());
+        }
     }
 
     /// Return the line number corresponding to the byte index `offset`.
@@ -526,6 +583,7 @@ impl<'a> Iterator for Lines<'a> {
 #[cfg(test)]
 mod tests {
     use rope::Rope;
+    use unicode_segmentation::UnicodeSegmentation;
 
     #[test]
     fn replace_small() {
@@ -568,4 +626,45 @@ mod tests {
         */
     }
 
+    #[test]
+    fn grapheme_offsets() {
+        let a = Rope::from("ae\u{301}\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}o\u{308}\u{308}");
+        assert_eq!(Some(1), a.next_grapheme_offset(0));
+        assert_eq!(Some(4), a.next_grapheme_offset(1));
+        assert_eq!(Some(22), a.next_grapheme_offset(4));
+        assert_eq!(Some(27), a.next_grapheme_offset(22));
+        assert_eq!(None, a.next_grapheme_offset(27));
+        assert_eq!(Some(22), a.prev_grapheme_offset(27));
+        assert_eq!(Some(4), a.prev_grapheme_offset(22));
+        assert_eq!(Some(1), a.prev_grapheme_offset(4));
+        assert_eq!(Some(0), a.prev_grapheme_offset(1));
+        assert_eq!(None, a.prev_grapheme_offset(0));
+    }
+
+    #[test]
+    fn grapheme_offsets_across_leaves() {
+        let mut s = String::new();
+        for i in 0..500 {
+            s.push_str(["e\u{301}", "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}", "x", "\u{1F1EF}\u{1F1F5}"][i % 4]);
+        }
+        let a = Rope::from(&s);
+        let expected: Vec<usize> = s.grapheme_indices(true).map(|(offset, _)| offset).skip(1)
+            .chain(Some(s.len())).collect();
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        while let Some(next) = a.next_grapheme_offset(offset) {
+            offsets.push(next);
+            offset = next;
+        }
+        assert_eq!(expected, offsets);
+        let mut offsets = Vec::new();
+        while let Some(prev) = a.prev_grapheme_offset(offset) {
+            offsets.push(prev);
+            offset = prev;
+        }
+        offsets.reverse();
+        let expected: Vec<usize> = s.grapheme_indices(true).map(|(offset, _)| offset).collect();
+        assert_eq!(expected, offsets);
+    }
+
 }
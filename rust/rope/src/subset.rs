@@ -14,7 +14,7 @@
 
 //! A data structure for representing subsets of sequences (typically strings).
 
-use std::cmp::{max};
+use std::cmp::{max, min};
 
 // These two imports are for the `apply` method only.
 use tree::{Node, NodeInfo, TreeBuilder};
@@ -56,6 +56,13 @@ impl SubsetBuilder {
 }
 
 impl Subset {
+    /// Make a subset from raw ranges, without normalizing them, so that
+    /// tests can exercise inconsistent subsets.
+    #[cfg(test)]
+    pub fn from_ranges_for_test(ranges: Vec<(usize, usize)>) -> Subset {
+        Subset(ranges)
+    }
+
     /// Mostly for testing.
     pub fn delete_from_string(&self, s: &str) -> String {
         let mut result = String::new();
@@ -137,6 +144,27 @@ impl Subset {
         sb.build()
     }
 
+    /// Compute the intersection of two subsets. In other words, an element exists
+    /// in the resulting subset iff it exists in both of the inputs.
+    pub fn intersect(&self, other: &Subset) -> Subset {
+        let mut sb = SubsetBuilder::new();
+        let mut i = 0;
+        let mut j = 0;
+        while i < self.0.len() && j < other.0.len() {
+            let beg = max(self.0[i].0, other.0[j].0);
+            let end = min(self.0[i].1, other.0[j].1);
+            if beg < end {
+                sb.add_range(beg, end);
+            }
+            if self.0[i].1 < other.0[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        sb.build()
+    }
+
     /// Transform through coordinate transform represented by other.
     /// The equation satisfied is as follows:
     ///
@@ -464,4 +492,15 @@ mod tests {
         transform_case("01245689ABCDGJKLMPQSTWXYbcdfgjlmnosvy", "01245ABCDJLQSWXYgsv",
             "0123457ABCDEFHIJLNOQRSUVWXYZaeghikpqrstuvwxz");
     }
+
+    #[test]
+    fn intersect() {
+        let a = find_deletions("0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ", TEST_STR);
+        let b = find_deletions("0123456789abcdefghijklmnopqrstuvwxyz", TEST_STR);
+        assert!(a.intersect(&b).is_empty());
+        let a = find_deletions("0123456789ABCDEFGHIJ", TEST_STR);
+        let b = find_deletions("01234ABCDEFGHIJKLMNOPQRSTUVWXYZabc", TEST_STR);
+        assert_eq!("0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabc", a.intersect(&b).delete_from_string(TEST_STR));
+        assert_eq!(a.intersect(&b), b.intersect(&a));
+    }
 }
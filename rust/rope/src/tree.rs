@@ -176,6 +176,26 @@ impl<N: NodeInfo> Node<N> {
         self.0.len
     }
 
+    /// Determine whether the lengths and heights cached in this node and its
+    /// descendants agree with their contents. Nodes built through the public
+    /// API always do; this is for checking nodes from untrusted sources.
+    pub fn is_well_formed(&self) -> bool {
+        match self.0.val {
+            NodeVal::Leaf(ref l) => self.0.height == 0 && self.0.len == l.len(),
+            NodeVal::Internal(ref nodes) => {
+                !nodes.is_empty() &&
+                    nodes.iter().all(|n| n.0.height + 1 == self.0.height && n.is_well_formed()) &&
+                    nodes.iter().fold(0, |len, n| len + n.0.len) == self.0.len
+            }
+        }
+    }
+
+    // Overwrite the cached length, to simulate a corrupted node.
+    #[cfg(test)]
+    pub fn set_len_for_test(&mut self, len: usize) {
+        Arc::make_mut(&mut self.0).len = len;
+    }
+
     fn height(&self) -> usize {
         self.0.height
     }